@@ -0,0 +1,257 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{Method, header};
+use actix_web::middleware::Next;
+use actix_web::web::Data;
+use actix_web::HttpResponse;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use log::{error, info, warn};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::config::AzureConfig;
+use crate::models::status::{Error, Reply, Status};
+
+/// Role for the apps allowed to send utbetalinger and start load runs.
+pub const ROLE_LOAD_RUN: &str = "load-run";
+/// Role for the apps allowed to read status, reports and metrics.
+pub const ROLE_READ_ONLY: &str = "read-only";
+
+/// The probes, and metrics for the prometheus scraper, which has no azure token.
+const OPEN_PATHS: [&str; 3] = ["/health", "/ready", "/metrics"];
+
+/// Validates azure bearer tokens against the keys from JWKS and decides
+/// whether the calling app may do what it asks for.
+pub struct Auth {
+    keys: RwLock<HashMap<String, DecodingKey>>,
+    validation: Validation,
+    allowed_apps: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Claims {
+    azp: Option<String>,
+    azp_name: Option<String>,
+    #[serde(default)]
+    roles: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    Unauthorized(String),
+    Forbidden(String),
+}
+
+/// Auth from the standard nais azure env, or a local JWKS file instead of fetching it.
+/// None unless auth is turned on, which leaves every route open.
+pub fn auth(config: &AzureConfig) -> anyhow::Result<Option<Arc<Auth>>> {
+    if !config.auth_enabled() {
+        warn!("Auth is not enabled, every route is open to anyone");
+        return Ok(None);
+    }
+
+    let auth = Arc::new(Auth::new(&config.issuer, &config.client_id, config.allowed_apps.clone()));
+    if !config.jwks_file.is_empty() {
+        let path = &config.jwks_file;
+        let jwks = std::fs::read_to_string(path).map_err(|e| anyhow::anyhow!("AZURE_JWKS_FILE {path}: {e}"))?;
+        auth.set_keys(&serde_json::from_str(&jwks).map_err(|e| anyhow::anyhow!("AZURE_JWKS_FILE {path}: {e}"))?);
+    } else {
+        actix_web::rt::spawn(refresh_jwks(auth.clone(), config.jwks_uri.clone()));
+    }
+    Ok(Some(auth))
+}
+
+impl Auth {
+    pub fn new(issuer: &str, audience: &str, allowed_apps: Vec<String>) -> Self {
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.set_issuer(&[issuer]);
+        validation.set_audience(&[audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+        Self { keys: RwLock::new(HashMap::new()), validation, allowed_apps }
+    }
+
+    pub fn set_keys(&self, jwks: &JwkSet) {
+        let keys: HashMap<_, _> = jwks
+            .keys
+            .iter()
+            .filter_map(|jwk| {
+                let kid = jwk.common.key_id.clone()?;
+                match DecodingKey::from_jwk(jwk) {
+                    Ok(key) => Some((kid, key)),
+                    Err(e) => {
+                        warn!("Skipping jwk {kid} {:?}", e);
+                        None
+                    }
+                }
+            })
+            .collect();
+        info!("Validating tokens with {} keys from JWKS", keys.len());
+        *self.keys.write().unwrap() = keys;
+    }
+
+    /// Checks the signature, issuer, audience and expiry of the token.
+    pub fn validate(&self, token: &str) -> Result<Claims, AuthError> {
+        let header = jsonwebtoken::decode_header(token).map_err(|e| AuthError::Unauthorized(e.to_string()))?;
+        let kid = header.kid.ok_or_else(|| AuthError::Unauthorized("Token mangler kid".into()))?;
+        let keys = self.keys.read().unwrap();
+        let key = keys
+            .get(&kid)
+            .ok_or_else(|| AuthError::Unauthorized(format!("Ukjent nøkkel {kid}")))?;
+        jsonwebtoken::decode::<Claims>(token, key, &self.validation)
+            .map(|it| it.claims)
+            .map_err(|e| AuthError::Unauthorized(e.to_string()))
+    }
+
+    /// Reads need `read-only` or `load-run`, everything else needs `load-run`.
+    pub fn authorize(&self, claims: &Claims, method: &Method) -> Result<(), AuthError> {
+        let app = claims.azp_name.as_deref().or(claims.azp.as_deref()).unwrap_or("ukjent");
+        let allowed = self.allowed_apps.is_empty()
+            || [&claims.azp_name, &claims.azp]
+                .into_iter()
+                .flatten()
+                .any(|it| self.allowed_apps.contains(it));
+        if !allowed {
+            return Err(AuthError::Forbidden(format!("{app} har ikke tilgang")));
+        }
+
+        let read = matches!(*method, Method::GET | Method::HEAD);
+        let has_role = |role: &str| claims.roles.iter().any(|it| it == role);
+        if has_role(ROLE_LOAD_RUN) || (read && has_role(ROLE_READ_ONLY)) {
+            Ok(())
+        } else {
+            Err(AuthError::Forbidden(format!("{app} mangler rolle for {method}")))
+        }
+    }
+
+    fn check(&self, req: &ServiceRequest) -> Result<(), AuthError> {
+        let token = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|it| it.to_str().ok())
+            .and_then(|it| it.strip_prefix("Bearer "))
+            .ok_or_else(|| AuthError::Unauthorized("Mangler bearer token".into()))?;
+        let claims = self.validate(token.trim())?;
+        self.authorize(&claims, req.method())
+    }
+}
+
+impl AuthError {
+    fn response(&self) -> HttpResponse {
+        let (mut res, status_code, msg) = match self {
+            AuthError::Unauthorized(msg) => (HttpResponse::Unauthorized(), 401, msg),
+            AuthError::Forbidden(msg) => (HttpResponse::Forbidden(), 403, msg),
+        };
+        res.json(Reply {
+            status: Status::Feilet,
+            error: Some(Error {
+                status_code,
+                msg: msg.clone(),
+                doc: "https://helved-docs.ansatt.dev.nav.no/v3/doc/".into(),
+            }),
+        })
+    }
+}
+
+/// Middleware for every route except the probes, a no-op when `Data<Auth>` is not registered.
+pub async fn authenticate(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let denied = match req.app_data::<Data<Auth>>() {
+        Some(auth) if !OPEN_PATHS.contains(&req.path()) => auth.check(&req).err(),
+        _ => None,
+    };
+
+    match denied {
+        None => next.call(req).await.map(ServiceResponse::map_into_left_body),
+        Some(e) => {
+            info!("Denied {} {}: {:?}", req.method(), req.path(), e);
+            let res = e.response();
+            Ok(req.into_response(res).map_into_right_body())
+        }
+    }
+}
+
+/// Keeps the keys in sync with azure's JWKS, which rotates now and then.
+async fn refresh_jwks(auth: Arc<Auth>, uri: String) {
+    let http = reqwest::Client::new();
+    loop {
+        match http.get(&uri).send().await.and_then(|it| it.error_for_status()) {
+            Ok(res) => match res.json::<JwkSet>().await {
+                Ok(jwks) => auth.set_keys(&jwks),
+                Err(e) => error!("Failed to read JWKS from {uri} {:?}", e),
+            },
+            Err(e) => error!("Failed to fetch JWKS from {uri} {:?}", e),
+        }
+        actix_web::rt::time::sleep(Duration::from_secs(600)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::middleware::from_fn;
+    use actix_web::test::{TestRequest, call_service, init_service};
+    use actix_web::{App, web};
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::json;
+
+    const ISSUER: &str = "https://login.microsoftonline.com/test/v2.0";
+    const AUDIENCE: &str = "helved-performance";
+
+    fn token(app: &str, roles: &[&str], audience: &str, expires_in: i64) -> String {
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some("test".into());
+        let claims = json!({
+            "iss": ISSUER,
+            "aud": audience,
+            "exp": chrono::Utc::now().timestamp() + expires_in,
+            "azp": "client-id",
+            "azp_name": app,
+            "roles": roles,
+        });
+        let key = EncodingKey::from_rsa_pem(include_bytes!("../testdata/jwt-private.pem")).unwrap();
+        jsonwebtoken::encode(&header, &claims, &key).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn test_tokens_checked_against_jwks_and_roles() {
+        let auth = Auth::new(ISSUER, AUDIENCE, vec!["dev-gcp:helved:vedskiva".into()]);
+        auth.set_keys(&serde_json::from_str(include_str!("../testdata/jwks.json")).unwrap());
+        let app = init_service(
+            App::new()
+                .app_data(Data::new(auth))
+                .wrap(from_fn(authenticate))
+                .route("/health", web::get().to(HttpResponse::Ok))
+                .route("/metrics", web::get().to(HttpResponse::Ok))
+                .route("/loadgen", web::get().to(HttpResponse::Ok))
+                .route("/loadgen", web::post().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let vedskiva = "dev-gcp:helved:vedskiva";
+        let cases = [
+            (TestRequest::get().uri("/health"), None, 200),
+            (TestRequest::get().uri("/metrics"), None, 200),
+            (TestRequest::get().uri("/loadgen"), None, 401),
+            (TestRequest::get().uri("/loadgen"), Some(token(vedskiva, &[ROLE_READ_ONLY], AUDIENCE, 300)), 200),
+            (TestRequest::post().uri("/loadgen"), Some(token(vedskiva, &[ROLE_READ_ONLY], AUDIENCE, 300)), 403),
+            (TestRequest::post().uri("/loadgen"), Some(token(vedskiva, &[ROLE_LOAD_RUN], AUDIENCE, 300)), 200),
+            (TestRequest::post().uri("/loadgen"), Some(token("dev-gcp:helved:other", &[ROLE_LOAD_RUN], AUDIENCE, 300)), 403),
+            (TestRequest::post().uri("/loadgen"), Some(token(vedskiva, &[ROLE_LOAD_RUN], "other", 300)), 401),
+            (TestRequest::post().uri("/loadgen"), Some(token(vedskiva, &[ROLE_LOAD_RUN], AUDIENCE, -300)), 401),
+        ];
+
+        for (i, (req, token, expected)) in cases.into_iter().enumerate() {
+            let req = match token {
+                Some(token) => req.insert_header((header::AUTHORIZATION, format!("Bearer {token}"))),
+                None => req,
+            };
+            let res = call_service(&app, req.to_request()).await;
+            assert_eq!(res.status().as_u16(), expected, "case {i}");
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use dashmap::DashMap;
+use log::info;
+use serde::Deserialize;
+use std::fmt;
+use tokio::sync::Mutex;
+use tokio::time::{Duration, Instant};
+
+use crate::config::AzureConfig;
+
+/// Fetches access tokens with the client credentials grant, cached per scope
+/// and fetched again when they are about to expire.
+pub struct TokenProvider {
+    http: reqwest::Client,
+    token_endpoint: String,
+    client_id: String,
+    client_secret: String,
+    tokens: DashMap<String, Token>,
+    fetching: Mutex<()>,
+    refresh_before_expiry: Duration,
+}
+
+#[derive(Clone)]
+struct Token {
+    access_token: String,
+    expires_at: Instant,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+#[derive(Debug)]
+pub struct TokenError(String);
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Fikk ikke token fra azure: {}", self.0)
+    }
+}
+
+/// The token provider for the standard nais azure env, when azure is enabled.
+pub fn token_provider(config: &AzureConfig) -> Option<TokenProvider> {
+    if !config.tokens_enabled() {
+        return None;
+    }
+    info!("Fetching azure tokens for {} from {}", config.client_id, config.token_endpoint);
+    Some(TokenProvider::new(config.token_endpoint.clone(), config.client_id.clone(), config.client_secret.clone()))
+}
+
+impl TokenProvider {
+    pub fn new(token_endpoint: String, client_id: String, client_secret: String) -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .expect("Failed to create http client");
+        Self {
+            http,
+            token_endpoint,
+            client_id,
+            client_secret,
+            tokens: DashMap::new(),
+            fetching: Mutex::new(()),
+            refresh_before_expiry: Duration::from_secs(60),
+        }
+    }
+
+    pub async fn token(&self, scope: &str) -> Result<String, TokenError> {
+        if let Some(token) = self.cached(scope) {
+            return Ok(token);
+        }
+
+        let _fetching = self.fetching.lock().await;
+        if let Some(token) = self.cached(scope) {
+            return Ok(token);
+        }
+
+        let token = self.fetch(scope).await?;
+        self.tokens.insert(scope.to_owned(), token.clone());
+        Ok(token.access_token)
+    }
+
+    /// Adds the bearer token for the scope to an outbound request.
+    pub async fn authorize(&self, req: reqwest::RequestBuilder, scope: &str) -> Result<reqwest::RequestBuilder, TokenError> {
+        Ok(req.bearer_auth(self.token(scope).await?))
+    }
+
+    fn cached(&self, scope: &str) -> Option<String> {
+        self.tokens
+            .get(scope)
+            .filter(|it| Instant::now() + self.refresh_before_expiry < it.expires_at)
+            .map(|it| it.access_token.clone())
+    }
+
+    async fn fetch(&self, scope: &str) -> Result<Token, TokenError> {
+        let params = [
+            ("grant_type", "client_credentials"),
+            ("client_id", &self.client_id),
+            ("client_secret", &self.client_secret),
+            ("scope", scope),
+        ];
+        let res = self
+            .http
+            .post(&self.token_endpoint)
+            .form(&params)
+            .send()
+            .await
+            .map_err(|e| TokenError(e.to_string()))?;
+
+        let status = res.status();
+        if !status.is_success() {
+            let body = res.text().await.unwrap_or_default();
+            return Err(TokenError(format!("{status} {body}")));
+        }
+
+        let res: TokenResponse = res.json().await.map_err(|e| TokenError(e.to_string()))?;
+        info!("Fetched azure token for {scope}, expires in {}s", res.expires_in);
+        Ok(Token {
+            access_token: res.access_token,
+            expires_at: Instant::now() + Duration::from_secs(res.expires_in),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::web::{self, Data};
+    use actix_web::{App, HttpResponse, HttpServer};
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    /// Hands out numbered tokens, the ones for `short` expire within the refresh margin.
+    fn token_endpoint() -> (String, Data<AtomicU64>) {
+        let issued = Data::new(AtomicU64::new(0));
+        let counter = issued.clone();
+        let server = HttpServer::new(move || {
+            App::new().app_data(counter.clone()).route(
+                "/token",
+                web::post().to(|issued: Data<AtomicU64>, form: web::Form<HashMap<String, String>>| async move {
+                    assert_eq!(form["grant_type"], "client_credentials");
+                    let n = issued.fetch_add(1, Ordering::SeqCst);
+                    let expires_in = if form["scope"] == "short" { 30 } else { 3600 };
+                    HttpResponse::Ok().json(json!({
+                        "token_type": "Bearer",
+                        "expires_in": expires_in,
+                        "access_token": format!("token-{n}"),
+                    }))
+                }),
+            )
+        })
+        .workers(1)
+        .bind("127.0.0.1:0")
+        .unwrap();
+        let addr = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+        (format!("http://{addr}/token"), issued)
+    }
+
+    #[actix_web::test]
+    async fn test_tokens_cached_per_scope_and_refreshed_before_expiry() {
+        let (endpoint, issued) = token_endpoint();
+        let provider = TokenProvider::new(endpoint, "client".into(), "secret".into());
+
+        let first = provider.token("utsjekk").await.unwrap();
+        assert_eq!(provider.token("utsjekk").await.unwrap(), first, "Token should be cached");
+        assert_ne!(provider.token("other").await.unwrap(), first, "Each scope has its own token");
+        assert_eq!(issued.load(Ordering::SeqCst), 2);
+
+        let short = provider.token("short").await.unwrap();
+        assert_ne!(provider.token("short").await.unwrap(), short, "Token about to expire should be refreshed");
+        assert_eq!(issued.load(Ordering::SeqCst), 4);
+    }
+}
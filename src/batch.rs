@@ -0,0 +1,142 @@
+use actix_web::body;
+use futures::{StreamExt, stream};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tokio::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::models::{self, Fagsystem};
+use crate::models::status::{Error, Reply, Status};
+use crate::routes::{self, AppState};
+use crate::transport::TransportKind;
+
+/// One utbetaling in a batch, in the fagsystem's own json, tagged with the fagsystem.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Item {
+    fagsystem: Fagsystem,
+    transaction_id: Option<Uuid>,
+    utbetaling: serde_json::Value,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchResult {
+    total: usize,
+    parallelism: usize,
+    elapsed_ms: u64,
+    status_codes: BTreeMap<u16, usize>,
+    items: Vec<ItemResult>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ItemResult {
+    index: usize,
+    fagsystem: Option<Fagsystem>,
+    transaction_id: Option<Uuid>,
+    status_code: u16,
+    /// from the reply, none for a simulering
+    status: Option<Status>,
+    error: Option<Error>,
+    elapsed_ms: u64,
+}
+
+/// A JSON array of items, or JSONL with one item per line. An item that can't
+/// be read fails on its own, the rest of the batch is still sent.
+pub fn parse(body: &[u8]) -> Result<Vec<Result<Item, String>>, String> {
+    let text = std::str::from_utf8(body).map_err(|e| e.to_string())?;
+    let item = |json: serde_json::Result<Item>| json.map_err(|e| e.to_string());
+    if text.trim_start().starts_with('[') {
+        let items: Vec<serde_json::Value> = serde_json::from_str(text).map_err(|e| e.to_string())?;
+        Ok(items.into_iter().map(|it| item(serde_json::from_value(it))).collect())
+    } else {
+        Ok(text
+            .lines()
+            .filter(|it| !it.trim().is_empty())
+            .map(|line| item(serde_json::from_str(line)))
+            .collect())
+    }
+}
+
+/// Sends every item through `handle_utbetaling`, at most `parallelism` at once,
+/// and answers with the results in the order of the batch.
+pub async fn submit(
+    state: &AppState,
+    transport: TransportKind,
+    timeout: Duration,
+    parallelism: usize,
+    items: Vec<Result<Item, String>>,
+) -> BatchResult {
+    let started = Instant::now();
+    let items: Vec<ItemResult> = stream::iter(items.into_iter().enumerate())
+        .map(|(index, item)| send(state, transport, timeout, index, item))
+        .buffered(parallelism)
+        .collect()
+        .await;
+
+    let mut status_codes = BTreeMap::new();
+    for item in &items {
+        *status_codes.entry(item.status_code).or_default() += 1;
+    }
+    BatchResult {
+        total: items.len(),
+        parallelism,
+        elapsed_ms: started.elapsed().as_millis() as u64,
+        status_codes,
+        items,
+    }
+}
+
+async fn send(
+    state: &AppState,
+    transport: TransportKind,
+    timeout: Duration,
+    index: usize,
+    item: Result<Item, String>,
+) -> ItemResult {
+    let started = Instant::now();
+    let item = match item {
+        Ok(item) => item,
+        Err(msg) => return invalid(index, None, msg),
+    };
+    let utbetaling = match models::Utbetaling::from_json(item.fagsystem, item.utbetaling) {
+        Ok(utbetaling) => utbetaling,
+        Err(e) => return invalid(index, Some(item.fagsystem), e.to_string()),
+    };
+    let transaction_id = item.transaction_id.unwrap_or_else(Uuid::new_v4);
+    let dryrun = utbetaling.dryrun();
+
+    let res = routes::handle_utbetaling(state, transport, utbetaling, dryrun, transaction_id, timeout).await;
+    let status_code = res.status().as_u16();
+    let reply = body::to_bytes(res.into_body())
+        .await
+        .ok()
+        .and_then(|it| serde_json::from_slice::<Reply>(&it).ok());
+
+    ItemResult {
+        index,
+        fagsystem: Some(item.fagsystem),
+        transaction_id: Some(transaction_id),
+        status_code,
+        status: reply.as_ref().map(|it| it.status),
+        error: reply.and_then(|it| it.error),
+        elapsed_ms: started.elapsed().as_millis() as u64,
+    }
+}
+
+fn invalid(index: usize, fagsystem: Option<Fagsystem>, msg: String) -> ItemResult {
+    ItemResult {
+        index,
+        fagsystem,
+        transaction_id: None,
+        status_code: 400,
+        status: Some(Status::Feilet),
+        error: Some(Error {
+            status_code: 400,
+            msg,
+            doc: "https://helved-docs.ansatt.dev.nav.no/v3/doc/".into(),
+        }),
+        elapsed_ms: 0,
+    }
+}
@@ -0,0 +1,707 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::net::ToSocketAddrs;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::kafka::Partitioner;
+use crate::mock::{Latency, MockUtsjekk};
+use crate::models::Fagsystem;
+use crate::transport::TransportKind;
+
+/// Everything the service is set up with, loaded once at startup from the TOML
+/// file in `CONFIG_FILE`, if any, with env variables taking precedence.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bind_address: String,
+    pub transport: TransportKind,
+    pub kafka: KafkaConfig,
+    pub timeouts: Timeouts,
+    pub batch: BatchConfig,
+    pub results: ResultStoreConfig,
+    pub utsjekk: UtsjekkConfig,
+    pub mock_utsjekk: MockConfig,
+    pub azure: AzureConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KafkaConfig {
+    pub brokers: String,
+    pub security_protocol: SecurityProtocol,
+    pub ssl: SslConfig,
+    pub sasl: SaslConfig,
+    pub partitioner: Partitioner,
+    pub metadata_refresh_secs: u64,
+    pub topics: Topics,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum SecurityProtocol {
+    /// No encryption or authentication, for a local broker.
+    Plaintext,
+    /// Client certificates, as nais sets up for aiven.
+    #[default]
+    Ssl,
+    /// Username and password over TLS.
+    SaslSsl,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+pub enum SaslMechanism {
+    #[serde(rename = "PLAIN")]
+    Plain,
+    #[serde(rename = "SCRAM-SHA-256")]
+    ScramSha256,
+    #[default]
+    #[serde(rename = "SCRAM-SHA-512")]
+    ScramSha512,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SslConfig {
+    pub key_location: String,
+    pub certificate_location: String,
+    pub ca_location: String,
+}
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SaslConfig {
+    pub mechanism: SaslMechanism,
+    pub username: String,
+    pub password: String,
+}
+
+impl std::fmt::Debug for SaslConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SaslConfig")
+            .field("mechanism", &self.mechanism)
+            .field("username", &self.username)
+            .field("password", &"***")
+            .finish()
+    }
+}
+
+/// Topic names, the ones left out keep the helved defaults.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Topics {
+    pub status: String,
+    pub utbetaling: BTreeMap<Fagsystem, String>,
+    pub dryrun: BTreeMap<Fagsystem, String>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Timeouts {
+    /// how long a synchronous request waits for the first status, MOTTATT when all is well
+    pub mottatt_secs: u64,
+    /// how long a synchronous request waits for a final status or simulering
+    pub reply_secs: u64,
+    /// the most a request may ask for with `?timeout=` or `Prefer: wait=`
+    pub max_reply_secs: u64,
+    /// how long to wait for the broker to ack a produced utbetaling
+    pub produce_secs: u64,
+    /// how long utbetalinger in flight get to finish when the service shuts down
+    pub shutdown_grace_secs: u64,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BatchConfig {
+    /// utbetalinger in flight at once per batch, a request may ask for fewer with `?parallelism=`
+    pub parallelism: usize,
+    /// largest batch body accepted
+    pub max_bytes: usize,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResultStoreConfig {
+    /// transactions kept for `GET /abetal/status`, the oldest is evicted when full
+    pub capacity: usize,
+    pub ttl_secs: u64,
+}
+
+/// Utsjekk's http api, for the rest transport.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UtsjekkConfig {
+    pub url: String,
+    /// the azure scope for tokens to utsjekk, when azure is enabled
+    pub scope: String,
+    pub poll_interval_ms: u64,
+}
+
+/// Latency per step and failure rate for the mock utsjekk, which always answers
+/// with the memory transport and on kafka when enabled.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MockConfig {
+    pub enabled: bool,
+    pub mottatt_ms: Latency,
+    pub hos_oppdrag_ms: Latency,
+    pub kvittering_ms: Latency,
+    pub simulering_ms: Latency,
+    pub failure_rate: f64,
+}
+
+/// The standard nais azure env. Empty leaves outbound calls without a token, and
+/// every route is open until auth is turned on.
+#[derive(Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AzureConfig {
+    /// require azure tokens with the app roles, only once the calling apps are granted them in nais.yml
+    pub auth: bool,
+    pub client_id: String,
+    pub client_secret: String,
+    pub token_endpoint: String,
+    pub issuer: String,
+    pub jwks_uri: String,
+    /// local JWKS instead of fetching it from `jwks_uri`
+    pub jwks_file: String,
+    /// calling apps by `azp_name` or client id, empty allows every app
+    pub allowed_apps: Vec<String>,
+}
+
+impl std::fmt::Debug for AzureConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AzureConfig")
+            .field("auth", &self.auth)
+            .field("client_id", &self.client_id)
+            .field("client_secret", &"***")
+            .field("token_endpoint", &self.token_endpoint)
+            .field("issuer", &self.issuer)
+            .field("jwks_uri", &self.jwks_uri)
+            .field("jwks_file", &self.jwks_file)
+            .field("allowed_apps", &self.allowed_apps)
+            .finish()
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind_address: "127.0.0.1:8080".into(),
+            transport: TransportKind::Kafka,
+            kafka: KafkaConfig::default(),
+            timeouts: Timeouts::default(),
+            batch: BatchConfig::default(),
+            results: ResultStoreConfig::default(),
+            utsjekk: UtsjekkConfig::default(),
+            mock_utsjekk: MockConfig::default(),
+            azure: AzureConfig::default(),
+        }
+    }
+}
+
+impl Default for KafkaConfig {
+    fn default() -> Self {
+        Self {
+            brokers: String::new(),
+            security_protocol: SecurityProtocol::default(),
+            ssl: SslConfig::default(),
+            sasl: SaslConfig::default(),
+            partitioner: Partitioner::Murmur2,
+            metadata_refresh_secs: 60,
+            topics: Topics::default(),
+        }
+    }
+}
+
+impl Default for Topics {
+    fn default() -> Self {
+        Self { status: "helved.status.v1".into(), utbetaling: BTreeMap::new(), dryrun: BTreeMap::new() }
+    }
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self { parallelism: 16, max_bytes: 10 * 1024 * 1024 }
+    }
+}
+
+impl Default for ResultStoreConfig {
+    fn default() -> Self {
+        Self { capacity: 10_000, ttl_secs: 600 }
+    }
+}
+
+impl Default for UtsjekkConfig {
+    fn default() -> Self {
+        Self {
+            url: "http://utsjekk".into(),
+            scope: "api://dev-gcp.helved.utsjekk/.default".into(),
+            poll_interval_ms: 100,
+        }
+    }
+}
+
+impl Default for MockConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            mottatt_ms: Latency::Uniform(20, 50),
+            hos_oppdrag_ms: Latency::Uniform(50, 200),
+            kvittering_ms: Latency::Exponential(500.0),
+            simulering_ms: Latency::Exponential(300.0),
+            failure_rate: 0.01,
+        }
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self { mottatt_secs: 10, reply_secs: 30, max_reply_secs: 120, produce_secs: 5, shutdown_grace_secs: 20 }
+    }
+}
+
+impl FromStr for SecurityProtocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "PLAINTEXT" => Ok(SecurityProtocol::Plaintext),
+            "SSL" => Ok(SecurityProtocol::Ssl),
+            "SASL_SSL" => Ok(SecurityProtocol::SaslSsl),
+            other => Err(format!("unknown security protocol {other}, expected PLAINTEXT, SSL or SASL_SSL")),
+        }
+    }
+}
+
+impl SecurityProtocol {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SecurityProtocol::Plaintext => "plaintext",
+            SecurityProtocol::Ssl => "ssl",
+            SecurityProtocol::SaslSsl => "sasl_ssl",
+        }
+    }
+}
+
+impl FromStr for SaslMechanism {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_uppercase().as_str() {
+            "PLAIN" => Ok(SaslMechanism::Plain),
+            "SCRAM-SHA-256" => Ok(SaslMechanism::ScramSha256),
+            "SCRAM-SHA-512" => Ok(SaslMechanism::ScramSha512),
+            other => Err(format!("unknown sasl mechanism {other}, expected PLAIN, SCRAM-SHA-256 or SCRAM-SHA-512")),
+        }
+    }
+}
+
+impl SaslMechanism {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SaslMechanism::Plain => "PLAIN",
+            SaslMechanism::ScramSha256 => "SCRAM-SHA-256",
+            SaslMechanism::ScramSha512 => "SCRAM-SHA-512",
+        }
+    }
+}
+
+impl Topics {
+    pub fn utbetaling(&self, fagsystem: Fagsystem) -> &str {
+        &self.utbetaling[&fagsystem]
+    }
+
+    pub fn dryrun(&self, fagsystem: Fagsystem) -> &str {
+        &self.dryrun[&fagsystem]
+    }
+
+    fn with_defaults(&mut self) {
+        for fagsystem in Fagsystem::ALL {
+            let name = fagsystem.as_str();
+            self.utbetaling.entry(fagsystem).or_insert_with(|| format!("helved.utbetalinger-{name}.v1"));
+            self.dryrun.entry(fagsystem).or_insert_with(|| format!("helved.dryrun-{name}.v1"));
+        }
+    }
+}
+
+impl KafkaConfig {
+    pub fn metadata_refresh(&self) -> Duration {
+        Duration::from_secs(self.metadata_refresh_secs)
+    }
+}
+
+impl ResultStoreConfig {
+    pub fn ttl(&self) -> Duration {
+        Duration::from_secs(self.ttl_secs)
+    }
+}
+
+impl UtsjekkConfig {
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_interval_ms)
+    }
+}
+
+impl MockConfig {
+    pub fn utsjekk(&self) -> MockUtsjekk {
+        MockUtsjekk {
+            mottatt: self.mottatt_ms,
+            hos_oppdrag: self.hos_oppdrag_ms,
+            kvittering: self.kvittering_ms,
+            simulering: self.simulering_ms,
+            failure_rate: self.failure_rate,
+        }
+    }
+}
+
+impl AzureConfig {
+    /// Tokens to the service are validated only when asked for, nais sets the JWKS env either way.
+    pub fn auth_enabled(&self) -> bool {
+        self.auth
+    }
+
+    /// Outbound calls carry a token when the app has an azure client id.
+    pub fn tokens_enabled(&self) -> bool {
+        !self.client_id.is_empty()
+    }
+}
+
+impl Timeouts {
+    pub fn mottatt(&self) -> Duration {
+        Duration::from_secs(self.mottatt_secs)
+    }
+
+    pub fn reply(&self) -> Duration {
+        Duration::from_secs(self.reply_secs)
+    }
+
+    pub fn max_reply(&self) -> Duration {
+        Duration::from_secs(self.max_reply_secs)
+    }
+
+    /// The reply timeout a request asked for, capped by the max, or the configured one.
+    pub fn reply_or(&self, requested_secs: Option<u64>) -> Duration {
+        requested_secs.map_or(self.reply(), |it| Duration::from_secs(it.min(self.max_reply_secs)))
+    }
+
+    pub fn produce(&self) -> Duration {
+        Duration::from_secs(self.produce_secs)
+    }
+
+    pub fn shutdown_grace(&self) -> Duration {
+        Duration::from_secs(self.shutdown_grace_secs)
+    }
+}
+
+impl Config {
+    pub fn load() -> anyhow::Result<Self> {
+        let config = match std::env::var("CONFIG_FILE") {
+            Ok(path) => {
+                let toml = std::fs::read_to_string(&path).map_err(|e| anyhow::anyhow!("CONFIG_FILE {path}: {e}"))?;
+                Some(toml)
+            }
+            Err(_) => None,
+        };
+        Self::from_sources(config.as_deref(), |key| std::env::var(key).ok())
+    }
+
+    /// The TOML, then env on top, validated as a whole so every problem is reported at once.
+    fn from_sources(toml: Option<&str>, env: impl Fn(&str) -> Option<String>) -> anyhow::Result<Self> {
+        let mut config: Config = match toml {
+            Some(toml) => toml::from_str(toml).map_err(|e| anyhow::anyhow!("invalid config file: {e}"))?,
+            None => Config::default(),
+        };
+
+        let mut errors = Vec::new();
+        config.apply_env(&env, &mut errors);
+        config.kafka.topics.with_defaults();
+        errors.extend(config.problems());
+
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            anyhow::bail!("invalid config:\n  {}", errors.join("\n  "))
+        }
+    }
+
+    fn apply_env(&mut self, env: &impl Fn(&str) -> Option<String>, errors: &mut Vec<String>) {
+        fn set<T: FromStr>(env: &impl Fn(&str) -> Option<String>, errors: &mut Vec<String>, key: &str, target: &mut T)
+        where
+            T::Err: std::fmt::Display,
+        {
+            if let Some(value) = env(key) {
+                match value.parse() {
+                    Ok(value) => *target = value,
+                    Err(e) => errors.push(format!("{key}: {e}")),
+                }
+            }
+        }
+
+        set(env, errors, "BIND_ADDRESS", &mut self.bind_address);
+        set(env, errors, "TRANSPORT", &mut self.transport);
+        set(env, errors, "MOTTATT_TIMEOUT_SECS", &mut self.timeouts.mottatt_secs);
+        set(env, errors, "REPLY_TIMEOUT_SECS", &mut self.timeouts.reply_secs);
+        set(env, errors, "MAX_REPLY_TIMEOUT_SECS", &mut self.timeouts.max_reply_secs);
+        set(env, errors, "PRODUCE_TIMEOUT_SECS", &mut self.timeouts.produce_secs);
+        set(env, errors, "SHUTDOWN_GRACE_SECS", &mut self.timeouts.shutdown_grace_secs);
+        set(env, errors, "BATCH_PARALLELISM", &mut self.batch.parallelism);
+        set(env, errors, "BATCH_MAX_BYTES", &mut self.batch.max_bytes);
+        set(env, errors, "RESULT_STORE_CAPACITY", &mut self.results.capacity);
+        set(env, errors, "RESULT_STORE_TTL_SECS", &mut self.results.ttl_secs);
+        set(env, errors, "UTSJEKK_URL", &mut self.utsjekk.url);
+        set(env, errors, "UTSJEKK_SCOPE", &mut self.utsjekk.scope);
+        set(env, errors, "UTSJEKK_POLL_INTERVAL_MS", &mut self.utsjekk.poll_interval_ms);
+
+        let mock = &mut self.mock_utsjekk;
+        set(env, errors, "MOCK_UTSJEKK", &mut mock.enabled);
+        set(env, errors, "MOCK_UTSJEKK_MOTTATT_MS", &mut mock.mottatt_ms);
+        set(env, errors, "MOCK_UTSJEKK_HOS_OPPDRAG_MS", &mut mock.hos_oppdrag_ms);
+        set(env, errors, "MOCK_UTSJEKK_KVITTERING_MS", &mut mock.kvittering_ms);
+        set(env, errors, "MOCK_UTSJEKK_SIMULERING_MS", &mut mock.simulering_ms);
+        set(env, errors, "MOCK_UTSJEKK_FAILURE_RATE", &mut mock.failure_rate);
+
+        let azure = &mut self.azure;
+        set(env, errors, "AUTH_ENABLED", &mut azure.auth);
+        set(env, errors, "AZURE_APP_CLIENT_ID", &mut azure.client_id);
+        set(env, errors, "AZURE_APP_CLIENT_SECRET", &mut azure.client_secret);
+        set(env, errors, "AZURE_OPENID_CONFIG_TOKEN_ENDPOINT", &mut azure.token_endpoint);
+        set(env, errors, "AZURE_OPENID_CONFIG_ISSUER", &mut azure.issuer);
+        set(env, errors, "AZURE_OPENID_CONFIG_JWKS_URI", &mut azure.jwks_uri);
+        set(env, errors, "AZURE_JWKS_FILE", &mut azure.jwks_file);
+        if let Some(apps) = env("AUTH_ALLOWED_APPS") {
+            azure.allowed_apps = apps.split(',').map(str::trim).filter(|it| !it.is_empty()).map(str::to_owned).collect();
+        }
+
+        let kafka = &mut self.kafka;
+        set(env, errors, "KAFKA_BROKERS", &mut kafka.brokers);
+        set(env, errors, "KAFKA_SECURITY_PROTOCOL", &mut kafka.security_protocol);
+        set(env, errors, "KAFKA_PRIVATE_KEY_PATH", &mut kafka.ssl.key_location);
+        set(env, errors, "KAFKA_CERTIFICATE_PATH", &mut kafka.ssl.certificate_location);
+        set(env, errors, "KAFKA_CA_PATH", &mut kafka.ssl.ca_location);
+        set(env, errors, "KAFKA_SASL_MECHANISM", &mut kafka.sasl.mechanism);
+        set(env, errors, "KAFKA_SASL_USERNAME", &mut kafka.sasl.username);
+        set(env, errors, "KAFKA_SASL_PASSWORD", &mut kafka.sasl.password);
+        set(env, errors, "KAFKA_PARTITIONER", &mut kafka.partitioner);
+        set(env, errors, "KAFKA_METADATA_REFRESH_SECS", &mut kafka.metadata_refresh_secs);
+        set(env, errors, "KAFKA_STATUS_TOPIC", &mut kafka.topics.status);
+        for fagsystem in Fagsystem::ALL {
+            let name = fagsystem.as_str().to_uppercase();
+            if let Some(topic) = env(&format!("KAFKA_UTBETALING_TOPIC_{name}")) {
+                kafka.topics.utbetaling.insert(fagsystem, topic);
+            }
+            if let Some(topic) = env(&format!("KAFKA_DRYRUN_TOPIC_{name}")) {
+                kafka.topics.dryrun.insert(fagsystem, topic);
+            }
+        }
+    }
+
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if self.bind_address.to_socket_addrs().is_err() {
+            problems.push(format!("bind_address {} is not a valid address", self.bind_address));
+        }
+        if self.timeouts.reply_secs == 0 {
+            problems.push("timeouts.reply_secs must be greater than 0".into());
+        }
+        if self.timeouts.mottatt_secs == 0 || self.timeouts.mottatt_secs > self.timeouts.reply_secs {
+            problems.push("timeouts.mottatt_secs must be greater than 0 and at most timeouts.reply_secs".into());
+        }
+        if self.timeouts.max_reply_secs < self.timeouts.reply_secs {
+            problems.push("timeouts.max_reply_secs must be at least timeouts.reply_secs".into());
+        }
+        if self.timeouts.produce_secs == 0 {
+            problems.push("timeouts.produce_secs must be greater than 0".into());
+        }
+        if self.batch.parallelism == 0 {
+            problems.push("batch.parallelism must be greater than 0".into());
+        }
+        if self.timeouts.shutdown_grace_secs == 0 {
+            problems.push("timeouts.shutdown_grace_secs must be greater than 0".into());
+        }
+        if self.results.capacity == 0 {
+            problems.push("results.capacity (RESULT_STORE_CAPACITY) must be greater than 0".into());
+        }
+        if self.results.ttl_secs == 0 {
+            problems.push("results.ttl_secs (RESULT_STORE_TTL_SECS) must be greater than 0".into());
+        }
+
+        if !(0.0..=1.0).contains(&self.mock_utsjekk.failure_rate) {
+            problems.push("mock_utsjekk.failure_rate (MOCK_UTSJEKK_FAILURE_RATE) must be between 0.0 and 1.0".into());
+        }
+
+        let azure = &self.azure;
+        if azure.auth_enabled() {
+            if azure.issuer.is_empty() {
+                problems.push("azure.issuer (AZURE_OPENID_CONFIG_ISSUER) is required to validate tokens".into());
+            }
+            if azure.client_id.is_empty() {
+                problems.push("azure.client_id (AZURE_APP_CLIENT_ID) is required to validate tokens".into());
+            }
+            if azure.jwks_uri.is_empty() && azure.jwks_file.is_empty() {
+                problems.push(
+                    "azure.jwks_uri (AZURE_OPENID_CONFIG_JWKS_URI) or azure.jwks_file (AZURE_JWKS_FILE) is required to validate tokens"
+                        .into(),
+                );
+            }
+            if !azure.jwks_file.is_empty() && !Path::new(&azure.jwks_file).exists() {
+                problems.push(format!("azure.jwks_file (AZURE_JWKS_FILE) {} does not exist", azure.jwks_file));
+            }
+        }
+
+        if self.transport == TransportKind::Memory {
+            return problems;
+        }
+
+        if azure.tokens_enabled() {
+            if azure.token_endpoint.is_empty() {
+                problems.push("azure.token_endpoint (AZURE_OPENID_CONFIG_TOKEN_ENDPOINT) is required to fetch tokens".into());
+            }
+            if azure.client_secret.is_empty() {
+                problems.push("azure.client_secret (AZURE_APP_CLIENT_SECRET) is required to fetch tokens".into());
+            }
+        }
+        if self.utsjekk.url.trim().is_empty() {
+            problems.push("utsjekk.url (UTSJEKK_URL) is required".into());
+        }
+        if self.utsjekk.poll_interval_ms == 0 {
+            problems.push("utsjekk.poll_interval_ms (UTSJEKK_POLL_INTERVAL_MS) must be greater than 0".into());
+        }
+
+        let kafka = &self.kafka;
+        if kafka.brokers.trim().is_empty() {
+            problems.push("kafka.brokers (KAFKA_BROKERS) is required".into());
+        }
+        if kafka.metadata_refresh_secs == 0 {
+            problems.push("kafka.metadata_refresh_secs must be greater than 0".into());
+        }
+        let ssl = &kafka.ssl;
+        let key_location = ("kafka.ssl.key_location (KAFKA_PRIVATE_KEY_PATH)", &ssl.key_location);
+        let certificate_location = ("kafka.ssl.certificate_location (KAFKA_CERTIFICATE_PATH)", &ssl.certificate_location);
+        let ca_location = ("kafka.ssl.ca_location (KAFKA_CA_PATH)", &ssl.ca_location);
+        let (required, optional) = match kafka.security_protocol {
+            SecurityProtocol::Plaintext => (vec![], vec![]),
+            SecurityProtocol::Ssl => (vec![key_location, certificate_location, ca_location], vec![]),
+            SecurityProtocol::SaslSsl => (vec![], vec![ca_location]),
+        };
+        for (key, path) in required.into_iter().chain(optional.into_iter().filter(|(_, path)| !path.is_empty())) {
+            if path.is_empty() {
+                problems.push(format!("{key} is required for {}", kafka.security_protocol.as_str().to_uppercase()));
+            } else if !Path::new(path).exists() {
+                problems.push(format!("{key} {path} does not exist"));
+            }
+        }
+        if kafka.security_protocol == SecurityProtocol::SaslSsl {
+            if kafka.sasl.username.is_empty() {
+                problems.push("kafka.sasl.username (KAFKA_SASL_USERNAME) is required for SASL_SSL".into());
+            }
+            if kafka.sasl.password.is_empty() {
+                problems.push("kafka.sasl.password (KAFKA_SASL_PASSWORD) is required for SASL_SSL".into());
+            }
+        }
+
+        let topics = &kafka.topics;
+        let all = std::iter::once(&topics.status).chain(topics.utbetaling.values()).chain(topics.dryrun.values());
+        if all.clone().any(|it| it.trim().is_empty()) {
+            problems.push("kafka.topics must not be empty".into());
+        }
+        let mut utbetaling: Vec<_> = topics.utbetaling.values().collect();
+        utbetaling.sort();
+        utbetaling.dedup();
+        if utbetaling.len() != topics.utbetaling.len() {
+            problems.push("kafka.topics.utbetaling must be one topic per fagsystem".into());
+        }
+
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_env_overrides_toml_and_problems_reported_together() {
+        let toml = r#"
+            bind_address = "0.0.0.0:8080"
+
+            [kafka]
+            brokers = "localhost:9092"
+            partitioner = "xxhash"
+
+            [kafka.topics.dryrun]
+            aap = "local.dryrun-aap"
+        "#;
+        let env: HashMap<&str, &str> = HashMap::from([
+            ("KAFKA_DRYRUN_TOPIC_DP", "local.dryrun-dp"),
+            ("REPLY_TIMEOUT_SECS", "10"),
+            ("TRANSPORT", "memory"),
+            ("MOCK_UTSJEKK_MOTTATT_MS", "5-10"),
+            ("AUTH_ALLOWED_APPS", "dev-gcp:helved:vedskiva, dev-gcp:helved:peisen"),
+        ]);
+        let config = Config::from_sources(Some(toml), |key| env.get(key).map(|it| it.to_string())).unwrap();
+
+        assert_eq!(config.bind_address, "0.0.0.0:8080");
+        assert_eq!(config.kafka.partitioner, Partitioner::XxHash);
+        assert_eq!(config.kafka.topics.dryrun(Fagsystem::Aap), "local.dryrun-aap");
+        assert_eq!(config.kafka.topics.dryrun(Fagsystem::Dp), "local.dryrun-dp");
+        assert_eq!(config.kafka.topics.utbetaling(Fagsystem::Ts), "helved.utbetalinger-ts.v1");
+        assert_eq!(config.timeouts.reply(), Duration::from_secs(10));
+        assert_eq!(config.timeouts.reply_or(Some(60)), Duration::from_secs(60));
+        assert_eq!(config.timeouts.reply_or(Some(600)), config.timeouts.max_reply(), "Requested timeout should be capped");
+        assert_eq!(config.mock_utsjekk.mottatt_ms, Latency::Uniform(5, 10));
+        assert_eq!(config.azure.allowed_apps, ["dev-gcp:helved:vedskiva", "dev-gcp:helved:peisen"]);
+
+        let env: HashMap<&str, &str> = HashMap::from([
+            ("REPLY_TIMEOUT_SECS", "soon"),
+            ("KAFKA_PARTITIONER", "random"),
+            ("RESULT_STORE_CAPACITY", "many"),
+            ("MOCK_UTSJEKK_KVITTERING_MS", "slow"),
+            ("MOCK_UTSJEKK_FAILURE_RATE", "NaN"),
+            ("UTSJEKK_POLL_INTERVAL_MS", "0"),
+            ("AZURE_APP_CLIENT_ID", "client"),
+            ("AUTH_ENABLED", "true"),
+        ]);
+        let err = Config::from_sources(None, |key| env.get(key).map(|it| it.to_string())).unwrap_err().to_string();
+        for expected in [
+            "REPLY_TIMEOUT_SECS",
+            "KAFKA_PARTITIONER",
+            "KAFKA_BROKERS",
+            "KAFKA_CA_PATH",
+            "RESULT_STORE_CAPACITY",
+            "MOCK_UTSJEKK_KVITTERING_MS",
+            "MOCK_UTSJEKK_FAILURE_RATE",
+            "UTSJEKK_POLL_INTERVAL_MS",
+            "AZURE_OPENID_CONFIG_ISSUER",
+            "AZURE_OPENID_CONFIG_JWKS_URI",
+            "AZURE_OPENID_CONFIG_TOKEN_ENDPOINT",
+            "AZURE_APP_CLIENT_SECRET",
+        ] {
+            assert!(err.contains(expected), "{expected} missing from {err}");
+        }
+
+        assert!(Config::from_sources(Some("[kafka]\nbrokerz = \"typo\""), |_| None).is_err());
+    }
+
+    #[test]
+    fn test_security_protocols_need_their_own_settings() {
+        let toml = |protocol: &str| format!("[kafka]\nbrokers = \"localhost:9092\"\nsecurity_protocol = \"{protocol}\"");
+
+        assert!(Config::from_sources(Some(&toml("PLAINTEXT")), |_| None).is_ok());
+
+        let err = Config::from_sources(Some(&toml("SASL_SSL")), |_| None).unwrap_err().to_string();
+        assert!(err.contains("KAFKA_SASL_USERNAME") && err.contains("KAFKA_SASL_PASSWORD"), "{err}");
+        assert!(!err.contains("KAFKA_CA_PATH"), "CA is optional for SASL_SSL: {err}");
+
+        let env: HashMap<&str, &str> = HashMap::from([
+            ("KAFKA_SASL_MECHANISM", "scram-sha-256"),
+            ("KAFKA_SASL_USERNAME", "user"),
+            ("KAFKA_SASL_PASSWORD", "hunter2"),
+        ]);
+        let config = Config::from_sources(Some(&toml("SASL_SSL")), |key| env.get(key).map(|it| it.to_string())).unwrap();
+        assert_eq!(config.kafka.sasl.mechanism, SaslMechanism::ScramSha256);
+        assert!(!format!("{config:?}").contains("hunter2"), "Password should not be logged");
+    }
+}
@@ -0,0 +1,150 @@
+use actix_web::web::Bytes;
+use futures::{Stream, stream};
+use serde::Serialize;
+use tokio::sync::watch;
+use tokio::time::{self, Duration, Instant};
+use uuid::Uuid;
+
+use crate::models::dryrun::Simulering;
+use crate::models::status::{Error, Reply, Status};
+use crate::pubsub::Timeline;
+use crate::routes::{AppState, Subscribed};
+
+/// Follows one transaction and yields every status reply and the simulering as
+/// server-sent events, until a final status, a simulering or the timeout.
+pub struct Events {
+    status_rx: watch::Receiver<Timeline>,
+    sim_rx: Option<watch::Receiver<Option<Simulering>>>,
+    _subscribed: Option<Subscribed>,
+    seen: usize,
+    timeout: Duration,
+    deadline: Instant,
+    done: bool,
+}
+
+enum Wake {
+    Changed,
+    Closed,
+    Timeout,
+}
+
+impl Events {
+    /// Follows the transaction until the deadline its request had, None when it is not in the store.
+    pub fn subscribe(state: &AppState, uid: Uuid) -> Option<Self> {
+        let entry = state.results.get(&uid)?;
+        let subscribed = state.subscribe(uid, true);
+        Some(Self {
+            status_rx: subscribed.status_rx.clone(),
+            sim_rx: subscribed.sim_rx.clone(),
+            _subscribed: Some(subscribed),
+            seen: 0,
+            timeout: entry.deadline.saturating_duration_since(entry.submitted),
+            deadline: entry.deadline,
+            done: false,
+        })
+    }
+
+    pub fn into_stream(self) -> impl Stream<Item = Result<Bytes, actix_web::Error>> {
+        stream::unfold(self, |mut events| async move {
+            let event = events.next().await?;
+            Some((Ok(event), events))
+        })
+    }
+
+    async fn next(&mut self) -> Option<Bytes> {
+        while !self.done {
+            let reply = self.status_rx.borrow_and_update().replies.get(self.seen).map(|(_, it)| it.clone());
+            if let Some(reply) = reply {
+                self.seen += 1;
+                self.done = reply.status.is_final();
+                return Some(event("status", &reply));
+            }
+
+            let simulering = self.sim_rx.as_mut().and_then(|it| it.borrow_and_update().clone());
+            if let Some(simulering) = simulering {
+                self.done = true;
+                return Some(event("simulering", &simulering));
+            }
+
+            match self.wait().await {
+                Wake::Changed => continue,
+                Wake::Closed => self.done = true,
+                Wake::Timeout => {
+                    self.done = true;
+                    let timeout_error = Reply {
+                        status: Status::Feilet,
+                        error: Some(Error {
+                            status_code: 408,
+                            msg: format!("Fikk ingen endelig status innen {} sec", self.timeout.as_secs()),
+                            doc: "https://helved-docs.ansatt.dev.nav.no/v3/doc/".into(),
+                        }),
+                    };
+                    return Some(event("timeout", &timeout_error));
+                }
+            }
+        }
+        None
+    }
+
+    async fn wait(&mut self) -> Wake {
+        let Events { status_rx, sim_rx, deadline, .. } = self;
+        let sim_changed = async {
+            match sim_rx.as_mut() {
+                Some(rx) => rx.changed().await,
+                None => std::future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            changed = status_rx.changed() => match changed {
+                Ok(()) => Wake::Changed,
+                Err(_) => Wake::Closed,
+            },
+            changed = sim_changed => match changed {
+                Ok(()) => Wake::Changed,
+                Err(_) => {
+                    *sim_rx = None;
+                    Wake::Changed
+                }
+            },
+            _ = time::sleep_until(*deadline) => Wake::Timeout,
+        }
+    }
+}
+
+fn event(name: &str, data: &impl Serialize) -> Bytes {
+    let data = serde_json::to_string(data).expect("failed to serialize");
+    Bytes::from(format!("event: {name}\ndata: {data}\n\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_stream_until_final_status() {
+        let (status_tx, status_rx) = watch::channel(Timeline::default());
+        let mut events = Events {
+            status_rx,
+            sim_rx: None,
+            _subscribed: None,
+            seen: 0,
+            timeout: Duration::from_secs(5),
+            deadline: Instant::now() + Duration::from_secs(5),
+            done: false,
+        };
+
+        for status in [Status::Mottatt, Status::HosOppdrag, Status::Ok] {
+            status_tx.send_modify(|timeline| timeline.push(Reply { status, error: None }));
+        }
+
+        let mut received = Vec::new();
+        while let Some(event) = events.next().await {
+            received.push(String::from_utf8(event.to_vec()).unwrap());
+        }
+
+        assert_eq!(received.len(), 3);
+        assert!(received[0].starts_with("event: status\ndata: {\"status\":\"MOTTATT\""));
+        assert!(received[2].starts_with("event: status\ndata: {\"status\":\"OK\""));
+    }
+}
@@ -1,234 +1,561 @@
-use crate::models::Utbetaling;
+use crate::models::{Fagsystem, Utbetaling};
 use crate::{
+    config::{Config, KafkaConfig, SecurityProtocol},
+    mock::{MockReply, MockUtsjekk},
     models::{self, status},
-    routes::{StatusPubSub, SimPubSub},
+    transport::{Inbox, ProduceError, Transport},
 };
+use dashmap::DashMap;
+use futures::{StreamExt, future::BoxFuture};
+use log::{error, info, warn};
 use actix_web::rt::time::sleep;
-use tokio::sync::mpsc;
-use log::{error, info};
 use rdkafka::{
-    ClientConfig, Message,
-    consumer::{BaseConsumer, Consumer},
-    producer::{FutureProducer, FutureRecord},
+    ClientConfig, Message, Offset,
+    consumer::{Consumer, DefaultConsumerContext, MessageStream, StreamConsumer},
+    error::KafkaResult,
+    message::BorrowedMessage,
+    producer::{FutureProducer, FutureRecord, Producer as _},
 };
-use std::{env, hash::Hasher, time::Duration};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{hash::Hasher, str::FromStr, sync::{Arc, Mutex}, time::Duration};
+use tokio::{sync::watch, task::JoinHandle};
 use twox_hash::XxHash32;
 use uuid::Uuid;
 
-const NUM_PARTITIONS: i32 = 3;
+#[derive(Clone)]
+pub struct Producer {
+    producer: FutureProducer,
+    config: Arc<KafkaConfig>,
+    produce_timeout: Duration,
+    partitions: Arc<DashMap<String, i32>>,
+    metadata_fetched: Arc<AtomicBool>,
+    consumers: Consumers,
+    /// whether the mock utsjekk consumer is expected among the consumers
+    mocking: Arc<AtomicBool>,
+    closing: Arc<watch::Sender<bool>>,
+    tasks: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
 
-pub async fn produce_utbetaling(uid: Uuid, utbet: Utbetaling) {
-    {
-        let (topic, producer, value) = match utbet {
-            Utbetaling::Aap(aap) => {
-                let topic = "helved.utbetalinger-aap.v1";
-                let producer = producer("produce-aap-utbetaling");
-                let value = serde_json::to_string(&aap).expect("failed to serialize");
-                (topic, producer, value)
-            }
-            Utbetaling::Dp(dp) => {
-                let topic = "helved.utbetalinger-dp.v1";
-                let producer = producer("produce-dp-utbetaling");
-                let value = serde_json::to_string(&dp).expect("failed to serialize");
-                (topic, producer, value)
-            }
-            Utbetaling::Ts(ts) => {
-                let topic = "helved.utbetalinger-ts.v1";
-                let producer = producer("produce-ts-utbetaling");
-                let value = serde_json::to_string(&ts).expect("failed to serialize");
-                (topic, producer, value)
-            }
-            Utbetaling::Tp(tp) => {
-                let topic = "helved.utbetalinger-tp.v1";
-                let producer = producer("produce-tp-utbetaling");
-                let value = serde_json::to_string(&tp).expect("failed to serialize");
-                (topic, producer, value)
-            },
-            Utbetaling::Historisk(historisk) => {
-                let topic = "helved.utbetalinger-historisk.v1";
-                let producer = producer("produce-historisk-utbetaling");
-                let value = serde_json::to_string(&historisk).expect("failed to serialize");
-                (topic, producer, value)
-            }
-        };
+/// The running consumers by client id, so health checks can see their assignments.
+type Consumers = Arc<DashMap<&'static str, Arc<StreamConsumer>>>;
 
-        let key = uid.to_string();
-        let record = FutureRecord::to(topic)
-            .key(&key)
-            .payload(&value)
-            .partition(partition(uid));
+const STATUS_CONSUMER: &str = "consume_status";
+const DRYRUN_CONSUMER: &str = "consume-dryruns";
+const MOCK_CONSUMER: &str = "mock-utsjekk";
 
-        match producer.send(record, Duration::from_secs(5)).await {
-            Ok(delivery) => info!("Record sent: {:?}", delivery),
-            Err((err, msg)) => error!("Failed to send record: {:?} msg: {:?}", err, msg),
-        };
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Details {
+    brokers: Vec<String>,
+    metadata_error: Option<String>,
+    partitions: BTreeMap<String, i32>,
+    consumers: BTreeMap<&'static str, Vec<Position>>,
+}
+
+#[derive(Serialize)]
+struct Position {
+    topic: String,
+    partition: i32,
+    /// next offset to consume, none before the first record is read
+    offset: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Partitioner {
+    /// Same as the default partitioner in the JVM clients used by helved.
+    Murmur2,
+    XxHash,
+    /// Leave the partition unset and let librdkafka's configured partitioner decide.
+    Librdkafka,
+}
+
+impl FromStr for Partitioner {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "murmur2" => Ok(Partitioner::Murmur2),
+            "xxhash" => Ok(Partitioner::XxHash),
+            "librdkafka" => Ok(Partitioner::Librdkafka),
+            other => Err(format!("unknown partitioner {other}, expected murmur2, xxhash or librdkafka")),
+        }
     }
 }
 
-pub async fn status_consumer(channel: StatusPubSub) {
-    let consumer = consumer("consume_status");
-    consumer
-        .subscribe(&["helved.status.v1"])
-        .expect("subscribe to status-topic");
+impl Transport for Producer {
+    fn produce(&self, uid: Uuid, utbetaling: Utbetaling) -> BoxFuture<'_, Result<(), ProduceError>> {
+        Box::pin(produce_utbetaling(self, uid, utbetaling))
+    }
 
-    loop {
-        if let Some(result) = consumer.poll(Duration::from_millis(50)) {
-            let record = match result {
-                Ok(record) => record,
-                Err(e) => {
-                    error!("failed to read record on helved.status.v1 {:?}", e);
-                    continue;
+    fn subscribe(&self, inbox: Inbox) {
+        let closing = self.closing.subscribe();
+        let status = status_consumer(self.config.clone(), self.consumers.clone(), closing.clone(), inbox.clone());
+        let dryrun = dryrun_consumer(self.config.clone(), self.consumers.clone(), closing, inbox);
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.push(actix_web::rt::spawn(status));
+        tasks.push(actix_web::rt::spawn(dryrun));
+    }
+
+    fn close(&self) -> BoxFuture<'_, ()> {
+        Box::pin(async move {
+            self.closing.send_replace(true);
+            let tasks: Vec<_> = self.tasks.lock().unwrap().drain(..).collect();
+            for task in tasks {
+                if let Err(e) = task.await {
+                    error!("kafka consumer task failed {:?}", e);
                 }
-            };
+            }
+        })
+    }
 
-            let uid = match record
-                .key()
-                .and_then(|it| std::str::from_utf8(it).ok())
-                .and_then(|it| Uuid::parse_str(it).ok())
-            {
-                Some(uid) => uid,
-                None => continue,
-            };
+    fn not_ready(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        if !self.metadata_fetched.load(Ordering::Relaxed) {
+            problems.push("kafka: har ikke hentet metadata fra brokerne".to_owned());
+        }
+        let mock = self.mocking.load(Ordering::Relaxed).then_some(MOCK_CONSUMER);
+        for name in [STATUS_CONSUMER, DRYRUN_CONSUMER].into_iter().chain(mock) {
+            let assigned = self
+                .consumers
+                .get(name)
+                .and_then(|it| it.assignment().ok())
+                .is_some_and(|it| it.count() > 0);
+            if !assigned {
+                problems.push(format!("kafka: {name} har ikke fått tildelt partisjoner"));
+            }
+        }
+        problems
+    }
 
-            let payload = match record.payload().and_then(|it| std::str::from_utf8(it).ok()) {
-                Some(payload) => payload,
-                None => continue,
-            };
+    fn details(&self) -> BoxFuture<'_, serde_json::Value> {
+        Box::pin(async move {
+            let producer = self.producer.clone();
+            let metadata = actix_web::rt::task::spawn_blocking(move || {
+                producer
+                    .client()
+                    .fetch_metadata(None, Duration::from_secs(5))
+                    .map(|it| it.brokers().iter().map(|b| format!("{}:{}", b.host(), b.port())).collect())
+                    .map_err(|e| e.to_string())
+            })
+            .await
+            .unwrap_or_else(|e| Err(e.to_string()));
 
-            let reply: status::Reply = match serde_json::from_str(payload) {
-                Ok(reply) => reply,
-                Err(e) => {
-                    error!("failed to deserialize status::Reply {:?}", e);
-                    continue;
-                }
+            let consumers = self
+                .consumers
+                .iter()
+                .map(|it| (*it.key(), positions(it.value())))
+                .collect();
+            let partitions = self.partitions.iter().map(|it| (it.key().clone(), *it.value())).collect();
+
+            let (brokers, metadata_error) = match metadata {
+                Ok(brokers) => (brokers, None),
+                Err(e) => (Vec::new(), Some(e)),
             };
+            let details = Details { brokers, metadata_error, partitions, consumers };
+            serde_json::to_value(details).expect("failed to serialize")
+        })
+    }
 
-            if let Some((tx, _)) = channel.lock().await.get(&uid) {
-                let _ = tx.send(reply).await;
-            }
+    fn flush(&self) {
+        info!("Flushing kafka producer");
+        if let Err(e) = self.producer.flush(self.produce_timeout) {
+            error!("Failed to flush kafka producer {:?}", e);
+        }
+    }
+}
+
+async fn produce_utbetaling(producer: &Producer, uid: Uuid, utbet: Utbetaling) -> Result<(), ProduceError> {
+    let topic = producer.config.topics.utbetaling(utbet.fagsystem());
+    let value = utbet.payload();
+
+    let num_partitions = match producer.partitions.get(topic) {
+        Some(num_partitions) => *num_partitions,
+        None => return Err(ProduceError::UnknownTopic(topic.to_owned())),
+    };
+
+    let key = uid.to_string();
+    let mut record = FutureRecord::to(topic)
+        .key(&key)
+        .payload(&value);
+
+    if let Some(partition) = partition(producer.config.partitioner, key.as_bytes(), num_partitions) {
+        record = record.partition(partition);
+    }
+
+    match producer.producer.send(record, producer.produce_timeout).await {
+        Ok(delivery) => {
+            info!("Record sent: {:?}", delivery);
+            Ok(())
         }
+        Err((err, msg)) => {
+            error!("Failed to send record: {:?} msg: {:?}", err, msg);
+            Err(ProduceError::Delivery(err.to_string()))
+        }
+    }
+}
+
+/// Keeps the cached partition count per utbetaling topic in sync with the brokers.
+pub async fn refresh_partitions(producer: Producer) {
+    loop {
+        sleep(producer.config.metadata_refresh()).await;
+        spawn_fetch_partitions(&producer).await;
+    }
+}
 
-        {
-            let mut map = channel.lock().await;
-            let mut uids_to_remove = Vec::new();
-
-            for (uid, (_, rx)) in map.iter_mut() {
-                match rx.try_recv() {
-                    Ok(_) => {
-                        info!("Cleanup signal received for UID: {}", uid);
-                        uids_to_remove.push(*uid);
-                    }
-                    Err(mpsc::error::TryRecvError::Disconnected) => {
-                        info!("Router sender disconnected fro UID: {}", uid);
-                        uids_to_remove.push(*uid);
-                    }
-                    Err(mpsc::error::TryRecvError::Empty) => {
-                        // channel is empty, continue waiting
-                    }
+/// The fetch blocks for up to 10s, so it runs off the async runtime.
+async fn spawn_fetch_partitions(producer: &Producer) {
+    let producer = producer.clone();
+    if let Err(e) = actix_web::rt::task::spawn_blocking(move || fetch_partitions(&producer)).await {
+        error!("metadata refresh task failed {:?}", e);
+    }
+}
+
+/// A failed fetch makes the service unready until the next one succeeds.
+fn fetch_partitions(producer: &Producer) {
+    let metadata = match producer.producer.client().fetch_metadata(None, Duration::from_secs(10)) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            error!("Failed to fetch kafka metadata {:?}", e);
+            producer.metadata_fetched.store(false, Ordering::Relaxed);
+            return;
+        }
+    };
+    producer.metadata_fetched.store(true, Ordering::Relaxed);
+
+    for topic in producer.config.topics.utbetaling.values() {
+        let num_partitions = metadata
+            .topics()
+            .iter()
+            .find(|it| it.name() == topic && it.error().is_none())
+            .map(|it| it.partitions().len() as i32)
+            .filter(|it| *it > 0);
+
+        match num_partitions {
+            Some(num_partitions) => {
+                if producer.partitions.insert(topic.to_owned(), num_partitions) != Some(num_partitions) {
+                    info!("Topic {topic} has {num_partitions} partitions");
                 }
             }
-
-            for uid in uids_to_remove {
-                map.remove(&uid);
-                info!("Cleane up channel for UID: {}", uid);
+            None => {
+                if producer.partitions.remove(topic).is_some() {
+                    warn!("Topic {topic} is no longer in kafka metadata");
+                } else {
+                    warn!("Topic {topic} not found in kafka metadata");
+                }
             }
         }
     }
+}
+
+async fn status_consumer(config: Arc<KafkaConfig>, consumers: Consumers, mut closing: watch::Receiver<bool>, inbox: Inbox) {
+    let consumer = Arc::new(consumer(&config, STATUS_CONSUMER));
+    consumers.insert(STATUS_CONSUMER, consumer.clone());
+    consumer
+        .subscribe(&[&config.topics.status])
+        .expect("subscribe to status-topic");
+
+    let mut stream = consumer.stream();
+    while let Some(result) = next(&mut stream, &mut closing).await {
+        let record = match result {
+            Ok(record) => record,
+            Err(e) => {
+                error!("failed to read record on {} {:?}", config.topics.status, e);
+                continue;
+            }
+        };
+
+        let Some(uid) = key(&record) else { continue };
+        let Some(payload) = payload(&record) else { continue };
 
-    // consumer.unsubscribe();
+        let reply: status::Reply = match serde_json::from_str(payload) {
+            Ok(reply) => reply,
+            Err(e) => {
+                error!("failed to deserialize status::Reply {:?}", e);
+                continue;
+            }
+        };
+
+        inbox.status(uid, reply);
+    }
+    drop(stream);
+    close(&consumers, STATUS_CONSUMER, &consumer);
 }
 
-pub async fn dryrun_consumer(simulering_pending: SimPubSub) {
-    let consumer = consumer("consume-dryruns");
+async fn dryrun_consumer(config: Arc<KafkaConfig>, consumers: Consumers, mut closing: watch::Receiver<bool>, inbox: Inbox) {
+    let topics: Vec<&str> = config.topics.dryrun.values().map(String::as_str).collect();
+    let consumer = Arc::new(consumer(&config, DRYRUN_CONSUMER));
+    consumers.insert(DRYRUN_CONSUMER, consumer.clone());
     consumer
-        .subscribe(&["helved.dryrun-aap.v1", "helved.dryrun-dp.v1"])
-        .expect("subscribe to topic dryrun-aap or dryrun-dp");
+        .subscribe(&topics)
+        .unwrap_or_else(|e| panic!("subscribe to dryrun topics {topics:?}: {e}"));
+    info!("Consuming simuleringer from {:?}", topics);
 
-    loop {
-        if let Some(result) = consumer.poll(Duration::from_millis(50)) {
-            let record = match result {
-                Ok(record) => record,
-                Err(e) => {
-                    error!(
-                        "failed to read record on helved.dryrun-aap.v1 or helved.dryrun-dp.v1 {:?}",
-                        e
-                    );
-                    continue;
-                }
-            };
+    let mut stream = consumer.stream();
+    while let Some(result) = next(&mut stream, &mut closing).await {
+        let record = match result {
+            Ok(record) => record,
+            Err(e) => {
+                error!("failed to read record on dryrun topics {:?}", e);
+                continue;
+            }
+        };
 
-            let uid = match record
-                .key()
-                .and_then(|it| std::str::from_utf8(it).ok())
-                .and_then(|it| Uuid::parse_str(it).ok())
-            {
-                Some(uid) => uid,
-                None => continue,
-            };
+        let Some(uid) = key(&record) else { continue };
+        let Some(payload) = payload(&record) else { continue };
 
-            let payload = match record.payload().and_then(|it| std::str::from_utf8(it).ok()) {
-                Some(payload) => payload,
-                None => continue,
-            };
+        let simulering: models::dryrun::Simulering = match serde_json::from_str(payload) {
+            Ok(simulering) => simulering,
+            Err(e) => {
+                error!("failed to deserialize models::dryrun::Simulering {:?}", e);
+                continue;
+            }
+        };
 
-            let simulering: models::dryrun::Simulering = match serde_json::from_str(payload) {
-                Ok(simulering) => simulering,
-                Err(e) => {
-                    error!("failed to deserialize models::dryrun::Simulering {:?}", e);
-                    continue;
-                }
-            };
+        inbox.simulering(uid, simulering);
+    }
+    drop(stream);
+    close(&consumers, DRYRUN_CONSUMER, &consumer);
+}
+
+/// The next record, or None once the transport is closing.
+async fn next<'a>(
+    stream: &mut MessageStream<'a, DefaultConsumerContext>,
+    closing: &mut watch::Receiver<bool>,
+) -> Option<KafkaResult<BorrowedMessage<'a>>> {
+    tokio::select! {
+        _ = closing.wait_for(|closing| *closing) => None,
+        next = stream.next() => next,
+    }
+}
+
+fn close(consumers: &Consumers, name: &'static str, consumer: &StreamConsumer) {
+    consumer.unsubscribe();
+    consumers.remove(name);
+    info!("Closed kafka consumer {name}");
+}
+
+impl Producer {
+    /// Answers every utbetaling on the utbetaling topics like utsjekk would, for runs without utsjekk.
+    /// The consumer is checked by `/ready` and closed with the other consumers.
+    pub fn mock_utsjekk(&self, mock: Arc<MockUtsjekk>) {
+        self.mocking.store(true, Ordering::Relaxed);
+        let task = mock_consumer(self.clone(), self.closing.subscribe(), mock);
+        self.tasks.lock().unwrap().push(actix_web::rt::spawn(task));
+    }
+}
 
-            if let Some(simulering_tx) = simulering_pending.lock().await.remove(&uid) {
-                let _ = simulering_tx.send(simulering).await;
+async fn mock_consumer(producer: Producer, mut closing: watch::Receiver<bool>, mock: Arc<MockUtsjekk>) {
+    let config = producer.config.clone();
+    let topics: Vec<&str> = config.topics.utbetaling.values().map(String::as_str).collect();
+    // Commits, so utbetalinger produced before it got partitions are answered without replaying the topics on restart.
+    let consumer = consumer_config(&config, MOCK_CONSUMER)
+        .set("auto.offset.reset", "earliest")
+        .set("enable.auto.commit", "true")
+        .create::<StreamConsumer>()
+        .unwrap_or_else(|_| panic!("Failed to create kafka consumer {MOCK_CONSUMER}"));
+    let consumer = Arc::new(consumer);
+    producer.consumers.insert(MOCK_CONSUMER, consumer.clone());
+    consumer
+        .subscribe(&topics)
+        .unwrap_or_else(|e| panic!("subscribe to utbetaling topics {topics:?}: {e}"));
+    info!("Mock utsjekk is answering utbetalinger with {:?}", mock);
+
+    let mut stream = consumer.stream();
+    while let Some(result) = next(&mut stream, &mut closing).await {
+        let record = match result {
+            Ok(record) => record,
+            Err(e) => {
+                error!("mock utsjekk failed to read record {:?}", e);
+                continue;
             }
-        }
+        };
 
-        sleep(Duration::from_millis(1)).await;
+        let Some(uid) = key(&record) else { continue };
+        let topics = &config.topics;
+        let Some(fagsystem) = Fagsystem::ALL.into_iter().find(|it| topics.utbetaling(*it) == record.topic()) else {
+            continue;
+        };
+        let dryrun = payload(&record)
+            .and_then(|it| serde_json::from_str::<serde_json::Value>(it).ok())
+            .and_then(|it| it.get("dryrun").and_then(serde_json::Value::as_bool))
+            .unwrap_or(false);
+
+        let replies = mock.replies(fagsystem, dryrun);
+        let producer = producer.clone();
+        actix_web::rt::spawn(async move {
+            for (delay, reply) in replies {
+                sleep(delay).await;
+                let topics = &producer.config.topics;
+                let (topic, value) = match reply {
+                    MockReply::Status(reply) => (topics.status.as_str(), serde_json::to_string(&reply)),
+                    MockReply::Simulering(simulering) => (topics.dryrun(fagsystem), serde_json::to_string(&simulering)),
+                };
+                let value = value.expect("failed to serialize");
+                let key = uid.to_string();
+                let record = FutureRecord::to(topic).key(&key).payload(&value);
+                if let Err((err, _)) = producer.producer.send(record, producer.produce_timeout).await {
+                    error!("mock utsjekk failed to send reply to {topic}: {:?}", err);
+                }
+            }
+        });
     }
+    drop(stream);
+    close(&producer.consumers, MOCK_CONSUMER, &consumer);
+}
 
-    // consumer.unsubscribe();
+fn positions(consumer: &StreamConsumer) -> Vec<Position> {
+    let Ok(assignment) = consumer.assignment() else { return Vec::new() };
+    let positions = consumer.position().ok();
+    assignment
+        .elements()
+        .iter()
+        .map(|it| {
+            let offset = positions
+                .as_ref()
+                .and_then(|p| p.find_partition(it.topic(), it.partition()))
+                .and_then(|p| match p.offset() {
+                    Offset::Offset(offset) => Some(offset),
+                    _ => None,
+                });
+            Position { topic: it.topic().to_owned(), partition: it.partition(), offset }
+        })
+        .collect()
 }
 
-fn producer(client_id: &str) -> FutureProducer {
-    ClientConfig::new()
-        .set("bootstrap.servers", env::var("KAFKA_BROKERS").expect("KAFKA_BROKERS"))
-        .set("client.id", client_id.to_owned())
-        .set("security.protocol", "ssl")
+fn key(record: &BorrowedMessage) -> Option<Uuid> {
+    record
+        .key()
+        .and_then(|it| std::str::from_utf8(it).ok())
+        .and_then(|it| Uuid::parse_str(it).ok())
+}
+
+fn payload<'a>(record: &'a BorrowedMessage) -> Option<&'a str> {
+    record.payload().and_then(|it| std::str::from_utf8(it).ok())
+}
+
+pub async fn producer(config: &Config, client_id: &str) -> Producer {
+    let kafka = &config.kafka;
+    let producer = client_config(kafka, client_id)
         .set("compression.codec", "snappy")
-        .set("ssl.key.location", env::var("KAFKA_PRIVATE_KEY_PATH").expect("KAFKA_PRIVATE_KEY_PATH"))
-        .set("ssl.certificate.location", env::var("KAFKA_CERTIFICATE_PATH").expect("KAFKA_CERTIFICATE_PATH"))
-        .set("ssl.ca.location", env::var("KAFKA_CA_PATH").expect("KAFKA_CA_PATH"))
         .create()
         .unwrap_or_else(|_| {
             error!("Failed to create kafka producer {client_id}");
             panic!("Failed to create kafka producer {client_id}")
-        })
+        });
+
+    let producer = Producer {
+        producer,
+        config: Arc::new(kafka.clone()),
+        produce_timeout: config.timeouts.produce(),
+        partitions: Arc::new(DashMap::new()),
+        metadata_fetched: Arc::new(AtomicBool::new(false)),
+        consumers: Arc::new(DashMap::new()),
+        mocking: Arc::new(AtomicBool::new(false)),
+        closing: Arc::new(watch::channel(false).0),
+        tasks: Arc::new(Mutex::new(Vec::new())),
+    };
+    spawn_fetch_partitions(&producer).await;
+    producer
 }
 
-fn consumer(client_id: &str) -> BaseConsumer {
-    ClientConfig::new()
-        .set("bootstrap.servers", env::var("KAFKA_BROKERS").expect("KAFKA_BROKERS"))
-        .set("client.id", client_id.to_owned())
+/// Reads from the latest offset without committing, replies from before the service started are of no use.
+fn consumer(config: &KafkaConfig, client_id: &str) -> StreamConsumer {
+    consumer_config(config, client_id)
+        .create()
+        .unwrap_or_else(|_| panic!("Failed to create kafka consumer {client_id}"))
+}
+
+fn consumer_config(config: &KafkaConfig, client_id: &str) -> ClientConfig {
+    let mut consumer = client_config(config, client_id);
+    consumer
         .set("group.id", format!("{}-consumer", &client_id))
         .set("auto.offset.reset", "latest")
         .set("enable.auto.commit", "false")
         .set("socket.keepalive.enable", "true")
         .set("session.timeout.ms", "90000")
-        .set("heartbeat.interval.ms", "10000")
-        .set("security.protocol", "ssl")
-        .set("ssl.key.location", env::var("KAFKA_PRIVATE_KEY_PATH").expect("KAFKA_PRIVATE_KEY_PATH"))
-        .set("ssl.certificate.location", env::var("KAFKA_CERTIFICATE_PATH").expect("KAFKA_CERTIFICATE_PATH"))
-        .set("ssl.ca.location", env::var("KAFKA_CA_PATH").expect("KAFKA_CA_PATH"))
-        .create()
-        .unwrap_or_else(|_| panic!("Failed to create kafka consumer {client_id}"))
+        .set("heartbeat.interval.ms", "10000");
+    consumer
 }
 
-fn partition(key: Uuid) -> i32 {
-    let mut hasher = XxHash32::with_seed(0); // seed 0 like kakfa's murmur2
-    hasher.write(key.to_string().as_bytes());
-    let hash = hasher.finish() as i32;
-    (hash & 0x7fffffff) % NUM_PARTITIONS // ensure positive result
+/// Brokers, client id and the settings for the configured security protocol.
+fn client_config(config: &KafkaConfig, client_id: &str) -> ClientConfig {
+    let mut client = ClientConfig::new();
+    client
+        .set("bootstrap.servers", &config.brokers)
+        .set("client.id", client_id.to_owned())
+        .set("security.protocol", config.security_protocol.as_str());
+
+    match config.security_protocol {
+        SecurityProtocol::Plaintext => {}
+        SecurityProtocol::Ssl => {
+            client
+                .set("ssl.key.location", &config.ssl.key_location)
+                .set("ssl.certificate.location", &config.ssl.certificate_location)
+                .set("ssl.ca.location", &config.ssl.ca_location);
+        }
+        SecurityProtocol::SaslSsl => {
+            client
+                .set("sasl.mechanism", config.sasl.mechanism.as_str())
+                .set("sasl.username", &config.sasl.username)
+                .set("sasl.password", &config.sasl.password);
+            if !config.ssl.ca_location.is_empty() {
+                client.set("ssl.ca.location", &config.ssl.ca_location);
+            }
+        }
+    }
+    client
+}
+
+fn partition(partitioner: Partitioner, key: &[u8], num_partitions: i32) -> Option<i32> {
+    let hash = match partitioner {
+        Partitioner::Murmur2 => murmur2(key),
+        Partitioner::XxHash => {
+            let mut hasher = XxHash32::with_seed(0);
+            hasher.write(key);
+            hasher.finish() as i32
+        }
+        Partitioner::Librdkafka => return None,
+    };
+    Some((hash & 0x7fffffff) % num_partitions) // toPositive like kafka's Utils
+}
+
+/// Port of `org.apache.kafka.common.utils.Utils.murmur2`.
+fn murmur2(data: &[u8]) -> i32 {
+    const SEED: u32 = 0x9747b28c;
+    const M: u32 = 0x5bd1e995;
+    const R: u32 = 24;
+
+    let mut h = SEED ^ data.len() as u32;
+
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        let mut k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h = h.wrapping_mul(M);
+        h ^= k;
+    }
+
+    let tail = chunks.remainder();
+    if tail.len() >= 3 {
+        h ^= (tail[2] as u32) << 16;
+    }
+    if tail.len() >= 2 {
+        h ^= (tail[1] as u32) << 8;
+    }
+    if !tail.is_empty() {
+        h ^= tail[0] as u32;
+        h = h.wrapping_mul(M);
+    }
+
+    h ^= h >> 13;
+    h = h.wrapping_mul(M);
+    h ^= h >> 15;
+    h as i32
 }
 
 #[cfg(test)]
@@ -237,9 +564,46 @@ mod tests {
 
     #[test]
     fn test_partition_consistency() {
-        let key = Uuid::new_v4();
-        let p1 = partition(key);
-        let p2 = partition(key);
-        assert_eq!(p1, p2, "Partitioning should be consistent for the same key");
+        let key = Uuid::new_v4().to_string();
+        for partitioner in [Partitioner::Murmur2, Partitioner::XxHash] {
+            let p1 = partition(partitioner, key.as_bytes(), 3);
+            let p2 = partition(partitioner, key.as_bytes(), 3);
+            assert_eq!(p1, p2, "Partitioning should be consistent for the same key");
+        }
+        assert_eq!(partition(Partitioner::Librdkafka, key.as_bytes(), 3), None);
+    }
+
+    #[test]
+    fn test_client_config_per_security_protocol() {
+        let mut config = KafkaConfig { brokers: "localhost:9092".into(), ..Default::default() };
+
+        config.security_protocol = SecurityProtocol::Plaintext;
+        let client = client_config(&config, "test");
+        assert_eq!(client.get("security.protocol"), Some("plaintext"));
+        assert_eq!(client.get("ssl.key.location"), None);
+
+        config.security_protocol = SecurityProtocol::SaslSsl;
+        config.sasl.username = "user".into();
+        let client = client_config(&config, "test");
+        assert_eq!(client.get("security.protocol"), Some("sasl_ssl"));
+        assert_eq!(client.get("sasl.mechanism"), Some("SCRAM-SHA-512"));
+        assert_eq!(client.get("sasl.username"), Some("user"));
+        assert_eq!(client.get("ssl.ca.location"), None);
+    }
+
+    #[test]
+    fn test_murmur2_java_vectors() {
+        // from kafka's UtilsTest.testMurmur2
+        let cases: [(&[u8], i32); 6] = [
+            (b"21", -973932308),
+            (b"foobar", -790332482),
+            (b"a-little-bit-long-string", -985981536),
+            (b"a-little-bit-longer-string", -1486304829),
+            (b"lkjh234lh9fiuh90y23oiuhsafujhadof229phr9h19h89h8", -58897971),
+            (b"abc", 479470107),
+        ];
+        for (key, expected) in cases {
+            assert_eq!(murmur2(key), expected, "murmur2 of {:?}", std::str::from_utf8(key));
+        }
     }
 }
@@ -0,0 +1,342 @@
+use actix_web::rt;
+use chrono::{DateTime, Datelike, Days, NaiveDate, Utc, Weekday};
+use log::{info, warn};
+use rand::distr::{Distribution, weighted::WeightedIndex};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Semaphore;
+use tokio::task::AbortHandle;
+use tokio::time::{self, Duration, Instant, MissedTickBehavior};
+use uuid::Uuid;
+
+use crate::models::{self, Fagsystem};
+use crate::routes::{self, AppState};
+use crate::transport::TransportKind;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunConfig {
+    /// utbetalinger per second
+    pub rate: f64,
+    pub duration_secs: u64,
+    /// max number of utbetalinger waiting for a final status at the same time
+    pub concurrency: usize,
+    #[serde(default)]
+    pub dryrun: bool,
+    /// relative weight per fagsystem, fagsystemer left out are not sent
+    #[serde(default = "default_mix")]
+    pub mix: BTreeMap<Fagsystem, u32>,
+    /// kafka, rest or memory, the default transport when left out
+    #[serde(default)]
+    pub transport: Option<TransportKind>,
+}
+
+fn default_mix() -> BTreeMap<Fagsystem, u32> {
+    Fagsystem::ALL
+        .into_iter()
+        .map(|fagsystem| (fagsystem, 1))
+        .collect()
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Report {
+    pub running: bool,
+    pub config: Option<RunConfig>,
+    pub started: Option<DateTime<Utc>>,
+    pub finished: Option<DateTime<Utc>>,
+    pub sent: u64,
+    pub completed: u64,
+    pub status_codes: BTreeMap<u16, u64>,
+    pub fagsystemer: BTreeMap<Fagsystem, u64>,
+}
+
+#[derive(Debug)]
+pub enum StartError {
+    AlreadyRunning,
+    Invalid(String),
+}
+
+impl std::fmt::Display for StartError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StartError::AlreadyRunning => write!(f, "Det kjører allerede en lasttest"),
+            StartError::Invalid(msg) => write!(f, "{msg}"),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct LoadGen {
+    report: Arc<Mutex<Report>>,
+    run: Mutex<Option<AbortHandle>>,
+}
+
+impl LoadGen {
+    pub fn start(&self, state: AppState, mut config: RunConfig) -> Result<(), StartError> {
+        validate(&config).map_err(StartError::Invalid)?;
+        let transport = *config.transport.get_or_insert(state.transports.default);
+        if state.transports.get(transport).is_none() {
+            return Err(StartError::Invalid(format!("Transport {} er ikke satt opp", transport.as_str())));
+        }
+
+        let mut run = self.run.lock().unwrap();
+        if self.report.lock().unwrap().running {
+            return Err(StartError::AlreadyRunning);
+        }
+
+        info!("Starting load run {:?}", config);
+        *self.report.lock().unwrap() = Report {
+            running: true,
+            config: Some(config.clone()),
+            started: Some(Utc::now()),
+            ..Default::default()
+        };
+
+        let handle = rt::spawn(drive(state, config, self.report.clone()));
+        *run = Some(handle.abort_handle());
+        Ok(())
+    }
+
+    pub fn stop(&self) {
+        if let Some(run) = self.run.lock().unwrap().take() {
+            run.abort();
+            let mut report = self.report.lock().unwrap();
+            if report.running {
+                warn!("Load run stopped after {} utbetalinger", report.sent);
+                report.running = false;
+                report.finished = Some(Utc::now());
+            }
+        }
+    }
+
+    pub fn report(&self) -> Report {
+        self.report.lock().unwrap().clone()
+    }
+}
+
+const MIN_RATE: f64 = 0.001;
+const MAX_RATE: f64 = 10_000.0;
+const MAX_DURATION_SECS: u64 = 24 * 60 * 60;
+const MAX_CONCURRENCY: usize = 10_000;
+
+fn validate(config: &RunConfig) -> Result<(), String> {
+    if !(MIN_RATE..=MAX_RATE).contains(&config.rate) {
+        return Err(format!("rate må være mellom {MIN_RATE} og {MAX_RATE}"));
+    }
+    if !(1..=MAX_DURATION_SECS).contains(&config.duration_secs) {
+        return Err(format!("durationSecs må være mellom 1 og {MAX_DURATION_SECS}"));
+    }
+    if !(1..=MAX_CONCURRENCY).contains(&config.concurrency) {
+        return Err(format!("concurrency må være mellom 1 og {MAX_CONCURRENCY}"));
+    }
+    if config.mix.values().all(|weight| *weight == 0) {
+        return Err("mix må ha minst ett fagsystem med vekt større enn 0".into());
+    }
+    Ok(())
+}
+
+/// Ends the run in the report should `drive` stop without finishing, unless a newer run has started since.
+struct Running {
+    report: Arc<Mutex<Report>>,
+    started: Option<DateTime<Utc>>,
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        let mut report = self.report.lock().unwrap_or_else(|e| e.into_inner());
+        if report.running && report.started == self.started {
+            warn!("Load run ended unexpectedly after {} utbetalinger", report.sent);
+            report.running = false;
+            report.finished = Some(Utc::now());
+        }
+    }
+}
+
+async fn drive(state: AppState, config: RunConfig, report: Arc<Mutex<Report>>) {
+    let started = report.lock().unwrap().started;
+    let _running = Running { report: report.clone(), started };
+    let (fagsystemer, weights): (Vec<Fagsystem>, Vec<u32>) = config.mix.iter().map(|(f, w)| (*f, *w)).unzip();
+    let mix = WeightedIndex::new(&weights).expect("validated mix");
+    let permits = Arc::new(Semaphore::new(config.concurrency));
+    let transport = config.transport.unwrap_or(state.transports.default);
+
+    let mut ticker = time::interval(Duration::from_secs_f64(1.0 / config.rate));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let deadline = Instant::now() + Duration::from_secs(config.duration_secs);
+
+    while ticker.tick().await < deadline {
+        let permit = permits.clone().acquire_owned().await.expect("semaphore is never closed");
+        let fagsystem = fagsystemer[mix.sample(&mut rand::rng())];
+        let utbetaling = generate(fagsystem, config.dryrun);
+
+        {
+            let mut report = report.lock().unwrap();
+            report.sent += 1;
+            *report.fagsystemer.entry(fagsystem).or_default() += 1;
+        }
+
+        let state = state.clone();
+        let report = report.clone();
+        let dryrun = utbetaling.dryrun();
+        rt::spawn(async move {
+            let res = routes::handle_utbetaling(&state, transport, utbetaling, dryrun, Uuid::new_v4(), state.timeouts.reply()).await;
+            let mut report = report.lock().unwrap();
+            report.completed += 1;
+            *report.status_codes.entry(res.status().as_u16()).or_default() += 1;
+            drop(permit);
+        });
+    }
+
+    let _ = permits.acquire_many(config.concurrency as u32).await;
+
+    let mut report = report.lock().unwrap();
+    info!("Load run finished, sent {} utbetalinger", report.sent);
+    report.running = false;
+    report.finished = Some(Utc::now());
+}
+
+/// Synthesizes a random, valid utbetaling for the fagsystem.
+pub fn generate(fagsystem: Fagsystem, dryrun: bool) -> models::Utbetaling {
+    let mut rng = rand::rng();
+    let sak_id = format!("LAST{}", rng.random_range(100_000..1_000_000));
+    let behandling_id = format!("B{}", rng.random_range(100_000_000..1_000_000_000u64));
+    let ident = format!("{:011}", rng.random_range(1_000_000_000..100_000_000_000u64));
+    let now = Utc::now();
+    let tom = now.date_naive() - Days::new(1);
+    let fom = tom - Days::new(13);
+    let meldeperiode = format!("{fom}-{tom}");
+    let beløp = rng.random_range(100..2000);
+
+    let value = match fagsystem {
+        Fagsystem::Aap => json!({
+            "dryrun": dryrun,
+            "sakId": sak_id,
+            "behandlingId": behandling_id,
+            "ident": ident,
+            "utbetalinger": weekdays(fom, tom).map(|dato| json!({
+                "meldeperiode": meldeperiode,
+                "dato": dato,
+                "sats": beløp,
+                "utbetaltBeløp": beløp,
+            })).collect::<Vec<_>>(),
+            "vedtakstidspunktet": now,
+            "saksbehandler": "A123456",
+            "beslutter": "B123456",
+        }),
+        Fagsystem::Dp => json!({
+            "dryrun": dryrun,
+            "sakId": sak_id,
+            "behandlingId": behandling_id,
+            "ident": ident,
+            "utbetalinger": weekdays(fom, tom).map(|dato| json!({
+                "meldeperiode": meldeperiode,
+                "dato": dato,
+                "sats": beløp,
+                "utbetaltBeløp": beløp,
+                "utbetalingstype": "Dagpenger",
+            })).collect::<Vec<_>>(),
+            "vedtakstidspunktet": now,
+            "saksbehandler": "A123456",
+            "beslutter": "B123456",
+        }),
+        Fagsystem::Ts => json!({
+            "dryrun": dryrun,
+            "id": Uuid::new_v4(),
+            "sakId": sak_id,
+            "behandlingId": behandling_id,
+            "personident": ident,
+            "stønad": "TILSYN_BARN_AAP",
+            "vedtakstidspunkt": now,
+            "periodetype": "UKEDAG",
+            "perioder": [{ "fom": fom, "tom": tom, "beløp": beløp }],
+            "brukFagområdeTillst": false,
+            "saksbehandler": "A123456",
+            "beslutter": "B123456",
+        }),
+        Fagsystem::Tp => json!({
+            "dryrun": dryrun,
+            "sakId": sak_id,
+            "behandlingId": behandling_id,
+            "personident": ident,
+            "stønad": "ARBEIDSTRENING",
+            "vedtakstidspunkt": now,
+            "perioder": [{
+                "meldeperiode": meldeperiode,
+                "fom": fom,
+                "tom": tom,
+                "barnetillegg": false,
+                "betalendeEnhet": null,
+                "beløp": beløp,
+            }],
+            "saksbehandler": "A123456",
+            "beslutter": "B123456",
+        }),
+        Fagsystem::Historisk => json!({
+            "dryrun": dryrun,
+            "id": Uuid::new_v4(),
+            "sakId": sak_id,
+            "behandlingId": behandling_id,
+            "personident": ident,
+            "stønad": "TILSKUDD_SMÅHJELPEMIDLER",
+            "vedtakstidspunkt": now,
+            "periodetype": "EN_GANG",
+            "perioder": [{ "fom": fom, "tom": fom, "beløp": beløp }],
+            "saksbehandler": "A123456",
+            "beslutter": "B123456",
+        }),
+    };
+
+    let utbetaling = match fagsystem {
+        Fagsystem::Aap => serde_json::from_value::<models::aap::Utbetaling>(value).map(Into::into),
+        Fagsystem::Dp => serde_json::from_value::<models::dp::Utbetaling>(value).map(Into::into),
+        Fagsystem::Ts => serde_json::from_value::<models::ts::Utbetaling>(value).map(Into::into),
+        Fagsystem::Tp => serde_json::from_value::<models::tp::Utbetaling>(value).map(Into::into),
+        Fagsystem::Historisk => serde_json::from_value::<models::historisk::Utbetaling>(value).map(Into::into),
+    };
+    utbetaling.expect("generated utbetaling matches model")
+}
+
+fn weekdays(fom: NaiveDate, tom: NaiveDate) -> impl Iterator<Item = NaiveDate> {
+    fom.iter_days()
+        .take_while(move |dato| *dato <= tom)
+        .filter(|dato| !matches!(dato.weekday(), Weekday::Sat | Weekday::Sun))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_bounds() {
+        let config = |rate: f64, duration_secs: u64, concurrency: usize| RunConfig {
+            rate,
+            duration_secs,
+            concurrency,
+            dryrun: false,
+            mix: default_mix(),
+            transport: None,
+        };
+        assert!(validate(&config(10.0, 60, 10)).is_ok());
+        for invalid in [
+            config(1e-20, 60, 10),
+            config(1e10, 60, 10),
+            config(f64::NAN, 60, 10),
+            config(10.0, u64::MAX, 10),
+            config(10.0, 60, usize::MAX),
+        ] {
+            assert!(validate(&invalid).is_err(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn test_generate_all_fagsystemer() {
+        for fagsystem in default_mix().into_keys() {
+            let utbetaling = generate(fagsystem, true);
+            assert!(utbetaling.dryrun(), "{fagsystem:?} should keep the dryrun flag");
+        }
+    }
+}
@@ -1,18 +1,38 @@
-use std::collections::HashMap;
-use std::sync::{Arc };
-use tokio::sync::Mutex;
-use actix_web::web::Data;
+use std::sync::Arc;
+use actix_web::web::{self, Data};
+use actix_web::middleware::from_fn;
 use actix_web::{App, HttpServer};
+use log::{info, warn};
 use log4rs::append::console::ConsoleAppender;
 use log4rs::config::*;
 use log4rs::encode::json::JsonEncoder;
 use log4rs::init_config;
 
-use crate::routes::{StatusPubSub, SimPubSub};
+use crate::config::Config;
+use crate::loadgen::LoadGen;
+use crate::metrics::Metrics;
+use crate::pubsub::Registry;
+use crate::routes::AppState;
+use crate::shutdown::Shutdown;
+use crate::store::ResultStore;
+use crate::transport::{InMemory, TransportKind, Transports};
 
+mod auth;
+mod azure;
+mod batch;
+mod config;
+mod events;
 mod models;
 mod kafka;
+mod loadgen;
+mod metrics;
+mod mock;
+mod pubsub;
+mod rest;
 mod routes;
+mod shutdown;
+mod store;
+mod transport;
 
 #[actix_web::main]
 async fn main() -> anyhow::Result<()> {
@@ -21,38 +41,111 @@ async fn main() -> anyhow::Result<()> {
 }
 
 pub async fn init_server() -> anyhow::Result<()> {
-    let host = env_or_default("BIND_ADDRESS", "127.0.0.1:8080");
+    let config = Config::load()?;
 
-    let status_pending: StatusPubSub = Arc::new(Mutex::new(HashMap::new()));
-    actix_web::rt::spawn(kafka::status_consumer(status_pending.clone()));
+    let transports = match config.transport {
+        TransportKind::Memory => Transports::new(config.transport)
+            .with(TransportKind::Memory, Arc::new(InMemory::new(Some(config.mock_utsjekk.utsjekk())))),
+        TransportKind::Kafka | TransportKind::Rest => {
+            let producer = kafka::producer(&config, "produce-utbetaling").await;
+            actix_web::rt::spawn(kafka::refresh_partitions(producer.clone()));
+            if config.mock_utsjekk.enabled {
+                producer.mock_utsjekk(Arc::new(config.mock_utsjekk.utsjekk()));
+            }
+            let token_provider = azure::token_provider(&config.azure).map(Arc::new);
+            Transports::new(config.transport)
+                .with(TransportKind::Kafka, Arc::new(producer))
+                .with(TransportKind::Rest, Arc::new(rest::client(&config.utsjekk, token_provider, config.timeouts.max_reply())))
+        }
+    };
 
-    let simulering_pending: SimPubSub = Arc::new(Mutex::new(HashMap::new()));
-    actix_web::rt::spawn(kafka::dryrun_consumer(simulering_pending.clone()));
+    let metrics = Arc::new(Metrics::default());
 
-    let _ = HttpServer::new(move || {
+    let results = Arc::new(ResultStore::new(config.results.capacity, config.results.ttl()));
+    actix_web::rt::spawn(store::sweeper(results.clone()));
+
+    let pending = Arc::new(Registry::new(config.timeouts.max_reply() * 2, metrics.clone()));
+    actix_web::rt::spawn(pubsub::sweeper(pending.clone()));
+
+    let state = Data::new(AppState {
+        transports,
+        pending,
+        results,
+        metrics,
+        timeouts: config.timeouts,
+        batch: config.batch,
+        shutdown: Arc::new(Shutdown::default()),
+    });
+    state.transports.subscribe(&state.inbox());
+    let loadgen = Data::new(LoadGen::default());
+    let auth = auth::auth(&config.azure)?.map(Data::from);
+
+    let server_state = state.clone();
+    let server_loadgen = loadgen.clone();
+    let server = HttpServer::new(move || {
         App::new()
-            .app_data(Data::new(status_pending.clone()))
-            .app_data(Data::new(simulering_pending.clone()))
+            .app_data(server_state.clone())
+            .app_data(server_loadgen.clone())
+            .app_data(web::PayloadConfig::new(config.batch.max_bytes))
+            .configure(|cfg| {
+                if let Some(auth) = &auth {
+                    cfg.app_data(auth.clone());
+                }
+            })
+            .wrap(from_fn(auth::authenticate))
+            .service(routes::abetal_status)
+            .service(routes::abetal_events)
+            .service(routes::abetal_late)
+            .service(routes::abetal_batch)
             .service(routes::abetal_dp)
             .service(routes::abetal_dp_tx)
             .service(routes::abetal_aap)
+            .service(routes::abetal_aap_tx)
             .service(routes::abetal_ts)
+            .service(routes::abetal_ts_tx)
             .service(routes::abetal_tp)
+            .service(routes::abetal_tp_tx)
             .service(routes::abetal_historisk)
+            .service(routes::abetal_historisk_tx)
+            .service(routes::loadgen_start)
+            .service(routes::loadgen_report)
+            .service(routes::loadgen_stop)
+            .service(routes::metrics)
             .service(routes::health)
+            .service(routes::health_details)
+            .service(routes::ready)
     })
-    .bind(&host)?
-    .run()
-    .await;
+    .bind(&config.bind_address)?
+    .disable_signals()
+    // Utbetalinger in flight are drained before the server stops, this only covers the other routes.
+    .shutdown_timeout(1)
+    .run();
 
-    Ok(())
-}
+    let handle = server.handle();
+    let grace = config.timeouts.shutdown_grace();
+    let draining = state.clone();
+    actix_web::rt::spawn(async move {
+        shutdown::signal().await;
+        info!("Shutting down, no new utbetalinger are accepted");
+        loadgen.stop();
+        draining.shutdown.begin();
+        let drained = draining.shutdown.drain(grace).await;
+        if drained {
+            info!("Every utbetaling in flight is done");
+        } else {
+            warn!("Gave up on utbetalinger in flight after {grace:?}");
+        }
+        handle.stop(drained).await;
+    });
+
+    server.await?;
+    info!("Http server stopped");
 
-pub fn env_or_default(key: &str, default: &str) -> String {
-    match std::env::var(key) {
-        Ok(val) => val,
-        Err(_) => default.to_owned(),
-    }
+    state.transports.flush();
+    state.transports.close().await;
+    info!("Shutdown complete");
+
+    Ok(())
 }
 
 fn init_logger() {
@@ -0,0 +1,240 @@
+use dashmap::DashMap;
+use hdrhistogram::Histogram;
+use serde::Serialize;
+use std::fmt::{self, Write};
+use std::hash::Hash;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::models::Fagsystem;
+use crate::transport::TransportKind;
+
+const QUANTILES: [f64; 5] = [0.5, 0.9, 0.95, 0.99, 0.999];
+const MAX_LATENCY_MICROS: u64 = 10 * 60 * 1_000_000;
+/// Within 1% is plenty for latencies, and keeps each histogram small in a 48Mi pod.
+const SIGNIFICANT_FIGURES: u8 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Outcome {
+    Ok,
+    Feilet,
+    Timeout,
+}
+
+impl Outcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Ok => "ok",
+            Outcome::Feilet => "feilet",
+            Outcome::Timeout => "timeout",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplyKind {
+    Status,
+    Simulering,
+}
+
+impl ReplyKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReplyKind::Status => "status",
+            ReplyKind::Simulering => "simulering",
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Metrics {
+    latency: DashMap<(TransportKind, Fagsystem, bool, Outcome), Histogram<u32>>,
+    stages: DashMap<(TransportKind, Fagsystem, &'static str), Histogram<u32>>,
+    produced: DashMap<(TransportKind, Fagsystem), u64>,
+    replies: DashMap<ReplyKind, u64>,
+    orphans: DashMap<ReplyKind, u64>,
+    late: DashMap<ReplyKind, Histogram<u32>>,
+    pending: AtomicUsize,
+}
+
+impl Metrics {
+    pub fn latency(&self, transport: TransportKind, fagsystem: Fagsystem, dryrun: bool, outcome: Outcome, elapsed: Duration) {
+        record(&self.latency, (transport, fagsystem, dryrun, outcome), elapsed);
+    }
+
+    /// Time spent reaching `stage` since the previous stage of the same transaction.
+    pub fn stage(&self, transport: TransportKind, fagsystem: Fagsystem, stage: &'static str, elapsed: Duration) {
+        record(&self.stages, (transport, fagsystem, stage), elapsed);
+    }
+
+    /// A reply that arrived after the timeout, `elapsed` since produce.
+    pub fn late(&self, kind: ReplyKind, elapsed: Duration) {
+        record(&self.late, kind, elapsed);
+    }
+
+    /// Transactions someone is waiting on right now.
+    pub fn pending(&self, pending: usize) {
+        self.pending.store(pending, Ordering::Relaxed);
+    }
+
+    pub fn produced(&self, transport: TransportKind, fagsystem: Fagsystem) {
+        *self.produced.entry((transport, fagsystem)).or_default() += 1;
+    }
+
+    pub fn reply(&self, kind: ReplyKind) {
+        *self.replies.entry(kind).or_default() += 1;
+    }
+
+    pub fn orphan(&self, kind: ReplyKind) {
+        *self.orphans.entry(kind).or_default() += 1;
+    }
+
+    /// Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        self.write(&mut out).expect("writing to a String never fails");
+        out
+    }
+
+    fn write(&self, out: &mut String) -> fmt::Result {
+        summary(
+            out,
+            "helved_performance_latency_seconds",
+            "Time from produce until the final response for an utbetaling.",
+            &self.latency,
+            |(transport, fagsystem, dryrun, outcome)| {
+                format!(
+                    "transport=\"{}\",fagsystem=\"{}\",dryrun=\"{dryrun}\",status=\"{}\"",
+                    transport.as_str(),
+                    fagsystem.as_str(),
+                    outcome.as_str()
+                )
+            },
+        )?;
+        summary(
+            out,
+            "helved_performance_stage_seconds",
+            "Time from the previous stage until an utbetaling reached this stage.",
+            &self.stages,
+            |(transport, fagsystem, stage)| {
+                format!("transport=\"{}\",fagsystem=\"{}\",stage=\"{stage}\"", transport.as_str(), fagsystem.as_str())
+            },
+        )?;
+
+        counter(
+            out,
+            "helved_performance_produced_records_total",
+            "Utbetalinger sent, by the transport they were sent with.",
+            &self.produced,
+            |(transport, fagsystem)| format!("transport=\"{}\",fagsystem=\"{}\"", transport.as_str(), fagsystem.as_str()),
+        )?;
+        counter(
+            out,
+            "helved_performance_replies_total",
+            "Replies received for a pending transaction.",
+            &self.replies,
+            |it| format!("type=\"{}\"", it.as_str()),
+        )?;
+        counter(
+            out,
+            "helved_performance_orphan_replies_total",
+            "Replies received for a transaction that is not pending.",
+            &self.orphans,
+            |it| format!("type=\"{}\"", it.as_str()),
+        )?;
+        summary(
+            out,
+            "helved_performance_late_reply_seconds",
+            "Time from produce until a reply that arrived after the timeout.",
+            &self.late,
+            |it| format!("type=\"{}\"", it.as_str()),
+        )?;
+        gauge(
+            out,
+            "helved_performance_pending_transactions",
+            "Transactions waiting for a reply.",
+            self.pending.load(Ordering::Relaxed),
+        )
+    }
+}
+
+fn record<K: Eq + Hash>(histograms: &DashMap<K, Histogram<u32>>, key: K, elapsed: Duration) {
+    histograms
+        .entry(key)
+        .or_insert_with(|| Histogram::new_with_bounds(1, MAX_LATENCY_MICROS, SIGNIFICANT_FIGURES).expect("valid histogram bounds"))
+        .saturating_record(elapsed.as_micros() as u64);
+}
+
+fn summary<K: Copy + Eq + Hash + Ord>(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    histograms: &DashMap<K, Histogram<u32>>,
+    labels: impl Fn(K) -> String,
+) -> fmt::Result {
+    writeln!(out, "# HELP {name} {help}")?;
+    writeln!(out, "# TYPE {name} summary")?;
+    let mut keys: Vec<K> = histograms.iter().map(|it| *it.key()).collect();
+    keys.sort();
+    for key in keys {
+        let Some(histogram) = histograms.get(&key) else { continue };
+        let labels = labels(key);
+        for quantile in QUANTILES {
+            let seconds = histogram.value_at_quantile(quantile) as f64 / 1e6;
+            writeln!(out, "{name}{{{labels},quantile=\"{quantile}\"}} {seconds}")?;
+        }
+        let sum = histogram.mean() * histogram.len() as f64 / 1e6;
+        writeln!(out, "{name}_sum{{{labels}}} {sum}")?;
+        writeln!(out, "{name}_count{{{labels}}} {}", histogram.len())?;
+    }
+    Ok(())
+}
+
+fn counter<K: Copy + Eq + Hash + Ord>(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    values: &DashMap<K, u64>,
+    labels: impl Fn(K) -> String,
+) -> fmt::Result {
+    writeln!(out, "# HELP {name} {help}")?;
+    writeln!(out, "# TYPE {name} counter")?;
+    let mut values: Vec<_> = values.iter().map(|it| (*it.key(), *it.value())).collect();
+    values.sort();
+    for (key, value) in values {
+        writeln!(out, "{name}{{{}}} {value}", labels(key))?;
+    }
+    Ok(())
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: usize) -> fmt::Result {
+    writeln!(out, "# HELP {name} {help}")?;
+    writeln!(out, "# TYPE {name} gauge")?;
+    writeln!(out, "{name} {value}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_prometheus_text() {
+        let metrics = Metrics::default();
+        metrics.latency(TransportKind::Kafka, Fagsystem::Dp, false, Outcome::Ok, Duration::from_millis(250));
+        metrics.latency(TransportKind::Kafka, Fagsystem::Dp, false, Outcome::Ok, Duration::from_millis(750));
+        metrics.produced(TransportKind::Rest, Fagsystem::Dp);
+        metrics.orphan(ReplyKind::Status);
+        metrics.late(ReplyKind::Status, Duration::from_secs(45));
+        metrics.pending(3);
+
+        let text = metrics.render();
+        let labels = "transport=\"kafka\",fagsystem=\"dp\",dryrun=\"false\",status=\"ok\"";
+        assert!(text.contains(&format!("helved_performance_latency_seconds_count{{{labels}}} 2")));
+        assert!(text.contains(&format!("helved_performance_latency_seconds{{{labels},quantile=\"0.99\"}} 0.75")));
+        assert!(text.contains("helved_performance_produced_records_total{transport=\"rest\",fagsystem=\"dp\"} 1"));
+        assert!(text.contains("helved_performance_orphan_replies_total{type=\"status\"} 1"));
+        assert!(text.contains("helved_performance_late_reply_seconds_count{type=\"status\"} 1"));
+        assert!(text.contains("helved_performance_pending_transactions 3"));
+    }
+}
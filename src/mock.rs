@@ -0,0 +1,177 @@
+use rand::Rng;
+use serde::Deserialize;
+use serde_json::json;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::models::Fagsystem;
+use crate::models::dryrun::Simulering;
+use crate::models::status::{Error, Reply, Status};
+
+/// Stand-in for utsjekk that answers utbetalinger the way utsjekk would,
+/// with configurable latency per step and failure rate.
+#[derive(Debug, Clone)]
+pub struct MockUtsjekk {
+    pub mottatt: Latency,
+    pub hos_oppdrag: Latency,
+    pub kvittering: Latency,
+    pub simulering: Latency,
+    /// share of utbetalinger that end in `Status::Feilet`, between 0.0 and 1.0
+    pub failure_rate: f64,
+}
+
+#[derive(Debug, Clone)]
+pub enum MockReply {
+    Status(Reply),
+    Simulering(Simulering),
+}
+
+/// Delay distribution in milliseconds, written as `50`, `20-80` or `exp:200`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Latency {
+    Fixed(u64),
+    Uniform(u64, u64),
+    Exponential(f64),
+}
+
+impl FromStr for Latency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid latency {s}, expected <ms>, <min>-<max> or exp:<mean>");
+        if let Some(mean) = s.strip_prefix("exp:") {
+            let mean: f64 = mean.trim().parse().map_err(|_| invalid())?;
+            if !mean.is_finite() || mean < 0.0 {
+                return Err(invalid());
+            }
+            return Ok(Latency::Exponential(mean));
+        }
+        match s.split_once('-') {
+            Some((min, max)) => {
+                let min = min.trim().parse().map_err(|_| invalid())?;
+                let max = max.trim().parse().map_err(|_| invalid())?;
+                if min > max {
+                    return Err(invalid());
+                }
+                Ok(Latency::Uniform(min, max))
+            }
+            None => s.trim().parse().map(Latency::Fixed).map_err(|_| invalid()),
+        }
+    }
+}
+
+impl TryFrom<String> for Latency {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl Latency {
+    pub fn sample(&self) -> Duration {
+        let mut rng = rand::rng();
+        let millis = match *self {
+            Latency::Fixed(ms) => ms as f64,
+            Latency::Uniform(min, max) => rng.random_range(min..=max) as f64,
+            Latency::Exponential(mean) => -mean * (1.0 - rng.random::<f64>()).ln(),
+        };
+        Duration::from_secs_f64(millis / 1000.0)
+    }
+}
+
+impl MockUtsjekk {
+    /// The replies for one utbetaling, each with the delay since the previous reply.
+    pub fn replies(&self, fagsystem: Fagsystem, dryrun: bool) -> Vec<(Duration, MockReply)> {
+        let failed = rand::rng().random_bool(self.failure_rate.clamp(0.0, 1.0));
+
+        if dryrun {
+            let reply = if failed {
+                MockReply::Status(feilet())
+            } else {
+                MockReply::Simulering(simulering(fagsystem))
+            };
+            return vec![(self.simulering.sample(), reply)];
+        }
+
+        let mut replies = vec![(self.mottatt.sample(), MockReply::Status(Reply { status: Status::Mottatt, error: None }))];
+        if failed {
+            replies.push((self.kvittering.sample(), MockReply::Status(feilet())));
+        } else {
+            replies.push((self.hos_oppdrag.sample(), MockReply::Status(Reply { status: Status::HosOppdrag, error: None })));
+            replies.push((self.kvittering.sample(), MockReply::Status(Reply { status: Status::Ok, error: None })));
+        }
+        replies
+    }
+}
+
+fn feilet() -> Reply {
+    Reply {
+        status: Status::Feilet,
+        error: Some(Error {
+            status_code: 400,
+            msg: "Mock utsjekk avviste utbetalingen".into(),
+            doc: "https://helved-docs.ansatt.dev.nav.no/v3/doc/".into(),
+        }),
+    }
+}
+
+fn simulering(fagsystem: Fagsystem) -> Simulering {
+    let today = chrono::Utc::now().date_naive();
+    serde_json::from_value(json!({
+        "perioder": [{
+            "fom": today,
+            "tom": today,
+            "utbetalinger": [{
+                "fagsystem": fagsystem.as_str().to_uppercase(),
+                "sakId": "mock",
+                "utbetalesTil": "12345678910",
+                "stønadstype": fagsystem.as_str().to_uppercase(),
+                "tidligereUtbetalt": 0,
+                "nyttBeløp": 1000,
+            }],
+        }],
+    }))
+    .expect("mock simulering matches model")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_latency() {
+        assert_eq!("50".parse(), Ok(Latency::Fixed(50)));
+        assert_eq!("20-80".parse(), Ok(Latency::Uniform(20, 80)));
+        assert_eq!("exp:200".parse(), Ok(Latency::Exponential(200.0)));
+        assert!("80-20".parse::<Latency>().is_err());
+        for invalid in ["exp:-5", "exp:NaN", "exp:inf"] {
+            assert!(invalid.parse::<Latency>().is_err(), "{invalid} should be rejected");
+        }
+    }
+
+    #[test]
+    fn test_replies_end_in_final_status() {
+        let mock = MockUtsjekk {
+            mottatt: Latency::Fixed(0),
+            hos_oppdrag: Latency::Fixed(0),
+            kvittering: Latency::Fixed(0),
+            simulering: Latency::Fixed(0),
+            failure_rate: 0.0,
+        };
+
+        let statuses: Vec<_> = mock
+            .replies(Fagsystem::Aap, false)
+            .into_iter()
+            .filter_map(|(_, reply)| match reply {
+                MockReply::Status(reply) => Some(reply.status),
+                MockReply::Simulering(_) => None,
+            })
+            .collect();
+        assert_eq!(statuses, vec![Status::Mottatt, Status::HosOppdrag, Status::Ok]);
+
+        let dryrun = mock.replies(Fagsystem::Ts, true);
+        assert!(matches!(dryrun.as_slice(), [(_, MockReply::Simulering(_))]));
+    }
+}
@@ -7,6 +7,76 @@ pub enum Utbetaling {
     Historisk(historisk::Utbetaling),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Fagsystem {
+    Aap,
+    Dp,
+    Ts,
+    Tp,
+    Historisk,
+}
+
+impl Fagsystem {
+    pub const ALL: [Fagsystem; 5] = [Fagsystem::Aap, Fagsystem::Dp, Fagsystem::Ts, Fagsystem::Tp, Fagsystem::Historisk];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Fagsystem::Aap => "aap",
+            Fagsystem::Dp => "dp",
+            Fagsystem::Ts => "ts",
+            Fagsystem::Tp => "tp",
+            Fagsystem::Historisk => "historisk",
+        }
+    }
+}
+
+impl Utbetaling {
+    pub fn fagsystem(&self) -> Fagsystem {
+        match self {
+            Utbetaling::Aap(_) => Fagsystem::Aap,
+            Utbetaling::Dp(_) => Fagsystem::Dp,
+            Utbetaling::Ts(_) => Fagsystem::Ts,
+            Utbetaling::Tp(_) => Fagsystem::Tp,
+            Utbetaling::Historisk(_) => Fagsystem::Historisk,
+        }
+    }
+
+    pub fn dryrun(&self) -> bool {
+        match self {
+            Utbetaling::Aap(u) => u.dryrun,
+            Utbetaling::Dp(u) => u.dryrun,
+            Utbetaling::Ts(u) => u.dryrun,
+            Utbetaling::Tp(u) => u.dryrun,
+            Utbetaling::Historisk(u) => u.dryrun,
+        }
+        .unwrap_or(false)
+    }
+
+    /// The fagsystem's own json, as sent to `/abetal/{fagsystem}`.
+    pub fn from_json(fagsystem: Fagsystem, json: serde_json::Value) -> serde_json::Result<Self> {
+        Ok(match fagsystem {
+            Fagsystem::Aap => Utbetaling::Aap(serde_json::from_value(json)?),
+            Fagsystem::Dp => Utbetaling::Dp(serde_json::from_value(json)?),
+            Fagsystem::Ts => Utbetaling::Ts(serde_json::from_value(json)?),
+            Fagsystem::Tp => Utbetaling::Tp(serde_json::from_value(json)?),
+            Fagsystem::Historisk => Utbetaling::Historisk(serde_json::from_value(json)?),
+        })
+    }
+
+    /// The fagsystem's own json, as utsjekk expects it on kafka and over http.
+    pub fn payload(&self) -> String {
+        let payload = match self {
+            Utbetaling::Aap(u) => serde_json::to_string(u),
+            Utbetaling::Dp(u) => serde_json::to_string(u),
+            Utbetaling::Ts(u) => serde_json::to_string(u),
+            Utbetaling::Tp(u) => serde_json::to_string(u),
+            Utbetaling::Historisk(u) => serde_json::to_string(u),
+        };
+        payload.expect("failed to serialize")
+    }
+}
+
 pub mod aap {
     use chrono::{DateTime, NaiveDate, Utc};
     use serde::{Deserialize, Serialize};
@@ -295,13 +365,13 @@ pub mod status
 {
     use serde::{Deserialize, Serialize};
 
-    #[derive(Serialize, Deserialize, Debug, Clone)]
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
     pub struct Reply {
         pub status: Status,
         pub error: Option<Error>,
     }
 
-    #[derive(Serialize, Deserialize, Debug, Clone)]
+    #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
     #[serde(rename_all = "camelCase")]
     pub struct Error {
         pub status_code: u16,
@@ -317,6 +387,21 @@ pub mod status
         Mottatt,
         HosOppdrag,
     }
+
+    impl Status {
+        pub fn as_str(&self) -> &'static str {
+            match self {
+                Status::Ok => "ok",
+                Status::Feilet => "feilet",
+                Status::Mottatt => "mottatt",
+                Status::HosOppdrag => "hos_oppdrag",
+            }
+        }
+
+        pub fn is_final(&self) -> bool {
+            matches!(self, Status::Ok | Status::Feilet)
+        }
+    }
 }
 
 pub mod dryrun
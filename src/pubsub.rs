@@ -0,0 +1,210 @@
+use dashmap::DashMap;
+use dashmap::mapref::entry::Entry;
+use log::{info, warn};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::watch;
+use tokio::time::{self, Duration, Instant};
+use uuid::Uuid;
+
+use crate::metrics::Metrics;
+use crate::models::dryrun::Simulering;
+use crate::models::status::Reply;
+
+/// Every status reply for a transaction, stamped with when it arrived.
+#[derive(Debug, Clone, Default)]
+pub struct Timeline {
+    pub replies: Vec<(Instant, Reply)>,
+}
+
+impl Timeline {
+    pub fn push(&mut self, reply: Reply) {
+        self.replies.push((Instant::now(), reply));
+    }
+
+    pub fn last(&self) -> Option<&Reply> {
+        self.replies.last().map(|(_, reply)| reply)
+    }
+}
+
+/// The transactions someone is waiting on, with the senders for their status
+/// replies and simulering. Each waiting request holds a `Subscription`, and the
+/// transaction is removed when the last one is dropped. The sweeper removes
+/// whatever outlives the TTL, should a subscription ever be leaked.
+pub struct Registry {
+    entries: DashMap<Uuid, Pending>,
+    next_id: AtomicU64,
+    ttl: Duration,
+    metrics: Arc<Metrics>,
+}
+
+struct Pending {
+    id: u64,
+    registered: Instant,
+    subscribers: usize,
+    status: watch::Sender<Timeline>,
+    simulering: watch::Sender<Option<Simulering>>,
+}
+
+/// Keeps the transaction registered until dropped.
+pub struct Subscription {
+    uid: Uuid,
+    id: u64,
+    registry: Arc<Registry>,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.registry.unsubscribe(self.uid, self.id);
+    }
+}
+
+impl Registry {
+    pub fn new(ttl: Duration, metrics: Arc<Metrics>) -> Self {
+        Self { entries: DashMap::new(), next_id: AtomicU64::new(0), ttl, metrics }
+    }
+
+    /// Subscribes to the transaction. The first subscriber registers it, later ones share its senders.
+    pub fn subscribe(
+        self: &Arc<Self>,
+        uid: Uuid,
+    ) -> (watch::Receiver<Timeline>, watch::Receiver<Option<Simulering>>, Subscription) {
+        let (status_rx, sim_rx, id) = match self.entries.entry(uid) {
+            Entry::Occupied(mut entry) => {
+                let pending = entry.get_mut();
+                pending.subscribers += 1;
+                (pending.status.subscribe(), pending.simulering.subscribe(), pending.id)
+            }
+            Entry::Vacant(entry) => {
+                let (status, status_rx) = watch::channel(Timeline::default());
+                let (simulering, sim_rx) = watch::channel(None);
+                let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+                entry.insert(Pending { id, registered: Instant::now(), subscribers: 1, status, simulering });
+                (status_rx, sim_rx, id)
+            }
+        };
+        self.metrics.pending(self.entries.len());
+        (status_rx, sim_rx, Subscription { uid, id, registry: self.clone() })
+    }
+
+    /// Returns false when nobody is waiting on the transaction.
+    pub fn status(&self, uid: &Uuid, reply: Reply) -> bool {
+        match self.entries.get(uid) {
+            Some(pending) => {
+                pending.status.send_modify(|timeline| timeline.push(reply));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns false when nobody is waiting on the transaction.
+    pub fn simulering(&self, uid: &Uuid, simulering: Simulering) -> bool {
+        match self.entries.get(uid) {
+            Some(pending) => {
+                pending.simulering.send_replace(Some(simulering));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Adds what reached the result store before the transaction was registered, unless
+    /// the timeline already ends with that reply because it was routed here as well.
+    pub fn catch_up(&self, uid: &Uuid, reply: Option<Reply>, simulering: Option<Simulering>) {
+        let Some(pending) = self.entries.get(uid) else {
+            return;
+        };
+        if let Some(reply) = reply {
+            pending.status.send_if_modified(|timeline| {
+                let behind = timeline.last() != Some(&reply);
+                if behind {
+                    timeline.push(reply);
+                }
+                behind
+            });
+        }
+        if let Some(simulering) = simulering {
+            pending.simulering.send_if_modified(|it| {
+                let behind = it.is_none();
+                if behind {
+                    *it = Some(simulering);
+                }
+                behind
+            });
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// A subscription outliving a sweep must not unsubscribe whoever registered the uid after it.
+    fn unsubscribe(&self, uid: Uuid, id: u64) {
+        if let Entry::Occupied(mut entry) = self.entries.entry(uid)
+            && entry.get().id == id
+        {
+            entry.get_mut().subscribers -= 1;
+            if entry.get().subscribers == 0 {
+                entry.remove();
+            }
+        }
+        self.metrics.pending(self.entries.len());
+    }
+
+    fn sweep(&self) {
+        let before = self.entries.len();
+        self.entries.retain(|_, pending| pending.registered.elapsed() < self.ttl);
+        let removed = before - self.entries.len();
+        if removed > 0 {
+            warn!("Removed {removed} pending transactions older than {:?}", self.ttl);
+        }
+        self.metrics.pending(self.entries.len());
+    }
+}
+
+pub async fn sweeper(registry: Arc<Registry>) {
+    info!("Sweeping pending transactions older than {:?}", registry.ttl);
+    let mut interval = time::interval(registry.ttl.min(Duration::from_secs(30)));
+    loop {
+        interval.tick().await;
+        registry.sweep();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::status::Status;
+
+    #[test]
+    fn test_removed_when_last_subscription_dropped() {
+        let registry = Arc::new(Registry::new(Duration::from_secs(60), Arc::new(Metrics::default())));
+        let uid = Uuid::new_v4();
+        let (_rx, _sim_rx, sub) = registry.subscribe(uid);
+        let (_other_rx, _other_sim_rx, other_sub) = registry.subscribe(uid);
+        assert_eq!(registry.len(), 1, "Subscribers to one transaction should share its entry");
+        drop(sub);
+        assert!(registry.status(&uid, Reply { status: Status::Mottatt, error: None }));
+        drop(other_sub);
+        assert!(registry.is_empty(), "Dropping the last subscription should unsubscribe uid");
+    }
+
+    #[test]
+    fn test_sweep_removes_expired_and_ignores_stale_subscriptions() {
+        let registry = Arc::new(Registry::new(Duration::ZERO, Arc::new(Metrics::default())));
+        let uid = Uuid::new_v4();
+        let (_rx, _sim_rx, stale) = registry.subscribe(uid);
+        registry.sweep();
+        assert!(registry.is_empty(), "Expired transaction should be swept");
+
+        let (_rx, _sim_rx, _current) = registry.subscribe(uid);
+        drop(stale);
+        assert_eq!(registry.len(), 1, "Swept subscription should not unsubscribe the new one");
+    }
+}
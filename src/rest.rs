@@ -0,0 +1,232 @@
+use futures::future::BoxFuture;
+use log::{error, info, warn};
+use reqwest::header::CONTENT_TYPE;
+use std::sync::{Arc, Mutex};
+use tokio::time::{self, Duration, Instant};
+use uuid::Uuid;
+
+use crate::azure::{TokenError, TokenProvider};
+use crate::config::UtsjekkConfig;
+use crate::models::Utbetaling;
+use crate::models::dryrun::Simulering;
+use crate::models::status::{Error, Reply, Status};
+use crate::transport::{Inbox, ProduceError, Transport};
+
+/// Sends utbetalinger to utsjekk's http api and polls the status endpoint
+/// until a final status, instead of going through kafka.
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    poll_interval: Duration,
+    poll_timeout: Duration,
+    auth: Option<(Arc<TokenProvider>, String)>,
+    inbox: Arc<Mutex<Option<Inbox>>>,
+}
+
+/// Polls for as long as the longest timeout a request may ask for.
+pub fn client(config: &UtsjekkConfig, token_provider: Option<Arc<TokenProvider>>, poll_timeout: Duration) -> Client {
+    let client = Client::new(config.url.clone(), config.poll_interval(), poll_timeout);
+    match token_provider {
+        Some(token_provider) => client.with_auth(token_provider, config.scope.clone()),
+        None => client,
+    }
+}
+
+impl Client {
+    pub fn new(base_url: String, poll_interval: Duration, poll_timeout: Duration) -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .expect("Failed to create http client");
+        let base_url = base_url.trim_end_matches('/').to_owned();
+        info!("Sending utbetalinger over http to {base_url}");
+        Self { http, base_url, poll_interval, poll_timeout, auth: None, inbox: Arc::new(Mutex::new(None)) }
+    }
+
+    /// Sends an azure token for the scope with every request.
+    pub fn with_auth(mut self, token_provider: Arc<TokenProvider>, scope: String) -> Self {
+        self.auth = Some((token_provider, scope));
+        self
+    }
+
+    async fn authorize(&self, req: reqwest::RequestBuilder) -> Result<reqwest::RequestBuilder, TokenError> {
+        match &self.auth {
+            Some((token_provider, scope)) => token_provider.authorize(req, scope).await,
+            None => Ok(req),
+        }
+    }
+
+    fn url(&self, utbetaling: &Utbetaling, uid: Uuid) -> String {
+        format!("{}/utbetalinger/{}/{uid}", self.base_url, utbetaling.fagsystem().as_str())
+    }
+
+    async fn send(&self, uid: Uuid, utbetaling: Utbetaling) -> Result<(), ProduceError> {
+        let url = self.url(&utbetaling, uid);
+        let req = self.http.post(&url).header(CONTENT_TYPE, "application/json").body(utbetaling.payload());
+        let res = self
+            .authorize(req)
+            .await
+            .map_err(ProduceError::Token)?
+            .send()
+            .await
+            .map_err(|e| ProduceError::Unavailable(e.to_string()))?;
+
+        let Some(inbox) = self.inbox.lock().unwrap().clone() else {
+            warn!("No inbox for replies from utsjekk, {uid} will not be answered");
+            return Ok(());
+        };
+
+        let status = res.status();
+        if !status.is_success() {
+            let msg = res.text().await.unwrap_or_default();
+            inbox.status(uid, feilet(status.as_u16(), msg));
+            return Ok(());
+        }
+
+        if utbetaling.dryrun() {
+            match res.json::<Simulering>().await {
+                Ok(simulering) => inbox.simulering(uid, simulering),
+                Err(e) => inbox.status(uid, feilet(502, format!("Ugyldig simulering fra utsjekk: {e}"))),
+            }
+        } else {
+            let status_url = format!("{url}/status");
+            actix_web::rt::spawn(poll(self.clone(), inbox, uid, status_url));
+        }
+        Ok(())
+    }
+}
+
+impl Transport for Client {
+    fn produce(&self, uid: Uuid, utbetaling: Utbetaling) -> BoxFuture<'_, Result<(), ProduceError>> {
+        Box::pin(self.send(uid, utbetaling))
+    }
+
+    fn subscribe(&self, inbox: Inbox) {
+        *self.inbox.lock().unwrap() = Some(inbox);
+    }
+
+    fn details(&self) -> BoxFuture<'_, serde_json::Value> {
+        Box::pin(async { serde_json::json!({ "url": self.base_url }) })
+    }
+}
+
+/// Delivers every new status until a final one, or gives up after the poll timeout.
+async fn poll(client: Client, inbox: Inbox, uid: Uuid, url: String) {
+    let deadline = Instant::now() + client.poll_timeout;
+    let mut last: Option<Status> = None;
+
+    while Instant::now() < deadline {
+        time::sleep(client.poll_interval).await;
+
+        let req = match client.authorize(client.http.get(&url)).await {
+            Ok(req) => req,
+            Err(e) => {
+                error!("Failed to poll status for {uid} {e}");
+                continue;
+            }
+        };
+        let res = match req.send().await {
+            Ok(res) if res.status().is_success() => res,
+            Ok(_) => continue,
+            Err(e) => {
+                error!("Failed to poll status for {uid} {:?}", e);
+                continue;
+            }
+        };
+        let reply: Reply = match res.json().await {
+            Ok(reply) => reply,
+            Err(e) => {
+                error!("failed to deserialize status::Reply {:?}", e);
+                continue;
+            }
+        };
+
+        if last != Some(reply.status) {
+            last = Some(reply.status);
+            let done = reply.status.is_final();
+            inbox.status(uid, reply);
+            if done {
+                return;
+            }
+        }
+    }
+    warn!("Gave up polling status for {uid} after {:?}", client.poll_timeout);
+}
+
+fn feilet(status_code: u16, msg: String) -> Reply {
+    Reply {
+        status: Status::Feilet,
+        error: Some(Error {
+            status_code,
+            msg,
+            doc: "https://helved-docs.ansatt.dev.nav.no/v3/doc/".into(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::web::{self, Data};
+    use actix_web::{App, HttpResponse, HttpServer};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::loadgen;
+    use crate::metrics::Metrics;
+    use crate::models::Fagsystem;
+    use crate::pubsub::Registry;
+    use crate::store::ResultStore;
+
+    /// Answers like utsjekk: accepts the utbetaling, then MOTTATT on the first poll and OK after that.
+    fn utsjekk() -> String {
+        let polls = Data::new(AtomicUsize::new(0));
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(polls.clone())
+                .route("/utbetalinger/{fagsystem}/{uid}", web::post().to(HttpResponse::Accepted))
+                .route(
+                    "/utbetalinger/{fagsystem}/{uid}/status",
+                    web::get().to(|polls: Data<AtomicUsize>| async move {
+                        let status = match polls.fetch_add(1, Ordering::SeqCst) {
+                            0 => Status::Mottatt,
+                            _ => Status::Ok,
+                        };
+                        HttpResponse::Ok().json(Reply { status, error: None })
+                    }),
+                )
+        })
+        .workers(1)
+        .bind("127.0.0.1:0")
+        .unwrap();
+        let addr = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+        format!("http://{addr}")
+    }
+
+    #[actix_web::test]
+    async fn test_send_and_poll_until_final_status() {
+        let client = Client::new(utsjekk(), Duration::from_millis(10), Duration::from_secs(5));
+        let metrics = Arc::new(Metrics::default());
+        let inbox = Inbox {
+            pending: Arc::new(Registry::new(Duration::from_secs(60), metrics.clone())),
+            results: Arc::new(ResultStore::new(10, Duration::from_secs(60))),
+            metrics,
+        };
+        client.subscribe(inbox.clone());
+
+        let uid = Uuid::new_v4();
+        inbox.results.insert(uid, Duration::from_secs(5));
+        client.produce(uid, loadgen::generate(Fagsystem::Dp, false)).await.unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let reply = inbox.results.get(&uid).and_then(|it| it.reply);
+            if reply.as_ref().is_some_and(|it| it.status == Status::Ok) {
+                break;
+            }
+            assert!(Instant::now() < deadline, "Expected OK from utsjekk, got {reply:?}");
+            time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+}
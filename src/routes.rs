@@ -1,125 +1,526 @@
 use actix_web::http::StatusCode;
+use actix_web::http::header::{self, HeaderName, HeaderValue};
 use actix_web::web::{self, Data};
-use actix_web::{HttpResponse, get, post};
+use actix_web::{HttpRequest, HttpResponse, delete, get, post};
 use futures::future::select_all;
-use tokio::sync::{mpsc, Mutex};
-use tokio::time::{self, Duration};
-use std::{
-    collections::HashMap,
-    sync::Arc,
-};
+use log::info;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::time::{self, Duration, Instant};
 use uuid::Uuid;
 
-use crate::kafka;
+use crate::batch;
+use crate::config::{BatchConfig, Timeouts};
+use crate::events::Events;
+use crate::loadgen::{LoadGen, RunConfig, StartError};
+use crate::metrics::{Metrics, Outcome};
 use crate::models;
 use crate::models::dryrun::Simulering;
 use crate::models::status::{Reply, Status, Error};
+use crate::shutdown::Shutdown;
+use crate::pubsub::{Registry, Subscription, Timeline};
+use crate::store::ResultStore;
+use crate::transport::{Inbox, ProduceError, TransportKind, Transports};
 
-pub type StatusPubSub = Arc<Mutex<HashMap<Uuid, (mpsc::Sender<models::status::Reply>, mpsc::Receiver<Uuid>)>>>;
-pub type SimPubSub = Arc<Mutex<HashMap<Uuid, mpsc::Sender<models::dryrun::Simulering>>>>;
+#[derive(Clone)]
+pub struct AppState {
+    pub transports: Transports,
+    pub pending: Arc<Registry>,
+    pub results: Arc<ResultStore>,
+    pub metrics: Arc<Metrics>,
+    pub timeouts: Timeouts,
+    pub batch: BatchConfig,
+    pub shutdown: Arc<Shutdown>,
+}
+
+/// Receivers for the replies to one transaction. Unregisters the transaction
+/// when dropped, if this was the last subscriber.
+pub struct Subscribed {
+    pub status_rx: watch::Receiver<Timeline>,
+    pub sim_rx: Option<watch::Receiver<Option<Simulering>>>,
+    _subscription: Subscription,
+}
+
+impl AppState {
+    pub fn inbox(&self) -> Inbox {
+        Inbox {
+            pending: self.pending.clone(),
+            results: self.results.clone(),
+            metrics: self.metrics.clone(),
+        }
+    }
+
+    /// What keeps the service from taking traffic, empty when ready.
+    pub fn not_ready(&self) -> Vec<String> {
+        let mut problems = self.transports.not_ready();
+        if self.shutdown.is_draining() {
+            problems.push("Shutting down".into());
+        }
+        problems
+    }
+
+    /// Subscribes to replies for the transaction, starting from what the result store already has.
+    /// Registers before reading the store, so a reply landing in between is found in one or the other.
+    pub fn subscribe(&self, uid: Uuid, dryrun: bool) -> Subscribed {
+        let (status_rx, sim_rx, _subscription) = self.pending.subscribe(uid);
+        if let Some(entry) = self.results.get(&uid) {
+            self.pending.catch_up(&uid, entry.reply, entry.simulering);
+        }
+        let sim_rx = dryrun.then_some(sim_rx);
+
+        Subscribed { status_rx, sim_rx, _subscription }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Submitted {
+    transaction_id: Uuid,
+}
+
+#[derive(Deserialize)]
+struct AbetalParams {
+    transport: Option<TransportKind>,
+    /// seconds to wait for the final status, capped by `timeouts.max_reply_secs`
+    timeout: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct BatchParams {
+    transport: Option<TransportKind>,
+    timeout: Option<u64>,
+    /// at most `batch.parallelism`
+    parallelism: Option<usize>,
+}
 
 #[get("/health")]
 pub async fn health() -> HttpResponse {
     HttpResponse::Ok().finish()
 }
 
+/// 503 until every transport can take traffic, so no reply is lost before
+/// the consumers have partitions.
+#[get("/ready")]
+pub async fn ready(state: Data<AppState>) -> HttpResponse {
+    let problems = state.not_ready();
+    if problems.is_empty() {
+        HttpResponse::Ok().finish()
+    } else {
+        HttpResponse::ServiceUnavailable().json(problems)
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HealthDetails {
+    ready: bool,
+    problems: Vec<String>,
+    pending: usize,
+    transports: BTreeMap<TransportKind, serde_json::Value>,
+}
+
+#[get("/health/details")]
+pub async fn health_details(state: Data<AppState>) -> HttpResponse {
+    let problems = state.not_ready();
+    HttpResponse::Ok().json(HealthDetails {
+        ready: problems.is_empty(),
+        problems,
+        pending: state.pending.len(),
+        transports: state.transports.details().await,
+    })
+}
+
+#[get("/metrics")]
+pub async fn metrics(state: Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(state.metrics.render())
+}
+
+#[post("/loadgen")]
+pub async fn loadgen_start(
+    state: Data<AppState>,
+    loadgen: Data<LoadGen>,
+    json: web::Json<RunConfig>,
+) -> HttpResponse {
+    match loadgen.start(state.get_ref().clone(), json.0) {
+        Ok(()) => HttpResponse::Accepted().json(loadgen.report()),
+        Err(e @ StartError::AlreadyRunning) => HttpResponse::Conflict().body(e.to_string()),
+        Err(e @ StartError::Invalid(_)) => HttpResponse::BadRequest().body(e.to_string()),
+    }
+}
+
+#[get("/loadgen")]
+pub async fn loadgen_report(loadgen: Data<LoadGen>) -> HttpResponse {
+    HttpResponse::Ok().json(loadgen.report())
+}
+
+#[delete("/loadgen")]
+pub async fn loadgen_stop(loadgen: Data<LoadGen>) -> HttpResponse {
+    loadgen.stop();
+    HttpResponse::Ok().json(loadgen.report())
+}
+
+#[get("/abetal/status/{transaction_id}")]
+pub async fn abetal_status(state: Data<AppState>, path: web::Path<Uuid>) -> HttpResponse {
+    let Some(entry) = state.results.get(&path.into_inner()) else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    if let Some(simulering) = entry.simulering {
+        return HttpResponse::Ok().json(simulering);
+    }
+
+    match entry.reply {
+        Some(reply) if reply.status.is_final() => reply_response(reply),
+        _ if Instant::now() > entry.deadline => {
+            let timeout = entry.deadline.saturating_duration_since(entry.submitted);
+            timed_out(format!("Fikk ingen endelig status innen {} sec", timeout.as_secs()))
+        }
+        Some(reply) => HttpResponse::Accepted().json(reply),
+        None => HttpResponse::Accepted().finish(),
+    }
+}
+
+/// Replies that came after the request timed out, or for a transaction nobody knew about.
+#[get("/abetal/late/{transaction_id}")]
+pub async fn abetal_late(state: Data<AppState>, path: web::Path<Uuid>) -> HttpResponse {
+    match state.results.late_replies(&path.into_inner()) {
+        Some(late) => HttpResponse::Ok().json(late),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[get("/abetal/{transaction_id}/events")]
+pub async fn abetal_events(state: Data<AppState>, path: web::Path<Uuid>) -> HttpResponse {
+    match Events::subscribe(&state, path.into_inner()) {
+        Some(events) => HttpResponse::Ok()
+            .content_type("text/event-stream")
+            .insert_header((header::CACHE_CONTROL, "no-cache"))
+            .streaming(events.into_stream()),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// Sends a JSON array or JSONL of utbetalinger tagged with their fagsystem, and answers
+/// with the result of each once every one of them is done.
+#[post("/abetal/batch")]
+pub async fn abetal_batch(state: Data<AppState>, req: HttpRequest, body: web::Bytes) -> HttpResponse {
+    let params = match web::Query::<BatchParams>::from_query(req.query_string()) {
+        Ok(params) => params.into_inner(),
+        Err(e) => return bad_request(e.to_string()),
+    };
+    let transport = params.transport.unwrap_or(state.transports.default);
+    let timeout = match reply_timeout(&state, &req, params.timeout) {
+        Ok(timeout) => timeout,
+        Err(msg) => return bad_request(msg.into()),
+    };
+    let parallelism = params.parallelism.unwrap_or(state.batch.parallelism).min(state.batch.parallelism);
+    if parallelism == 0 {
+        return bad_request("parallelism må være større enn 0".into());
+    }
+    let items = match batch::parse(&body) {
+        Ok(items) => items,
+        Err(msg) => return bad_request(msg),
+    };
+
+    info!("Sending batch of {} utbetalinger, {parallelism} at once", items.len());
+    HttpResponse::Ok().json(batch::submit(&state, transport, timeout, parallelism, items).await)
+}
+
 #[post("/abetal/aap")]
 pub async fn abetal_aap(
-    status_pubsub: Data<StatusPubSub>,
-    sim_pubsub: Data<SimPubSub>,
+    state: Data<AppState>,
+    req: HttpRequest,
     json: web::Json<models::aap::Utbetaling>,
 ) -> HttpResponse {
     let dryrun = json.0.dryrun.unwrap_or(false);
-    let tx = Uuid::new_v4();
-    handle_utbetaling(status_pubsub, sim_pubsub, json.0, dryrun, tx).await
+    abetal(&state, &req, json.0, dryrun, None).await
+}
+
+#[post("/abetal/aap/{transaction_id}")]
+pub async fn abetal_aap_tx(
+    state: Data<AppState>,
+    req: HttpRequest,
+    json: web::Json<models::aap::Utbetaling>,
+    path: web::Path<Uuid>,
+) -> HttpResponse {
+    let dryrun = json.0.dryrun.unwrap_or(false);
+    let tx: Uuid = path.into_inner();
+    abetal(&state, &req, json.0, dryrun, Some(tx)).await
 }
 
 #[post("/abetal/dp")]
 pub async fn abetal_dp(
-    status_pubsub: Data<StatusPubSub>,
-    sim_pubsub: Data<SimPubSub>,
+    state: Data<AppState>,
+    req: HttpRequest,
     json: web::Json<models::dp::Utbetaling>,
 ) -> HttpResponse {
     let dryrun = json.0.dryrun.unwrap_or(false);
-    let tx = Uuid::new_v4();
-    handle_utbetaling(status_pubsub, sim_pubsub, json.0, dryrun, tx).await
+    abetal(&state, &req, json.0, dryrun, None).await
 }
 
 #[post("/abetal/dp/{transaction_id}")]
 pub async fn abetal_dp_tx(
-    status_pubsub: Data<StatusPubSub>,
-    sim_pubsub: Data<SimPubSub>,
+    state: Data<AppState>,
+    req: HttpRequest,
     json: web::Json<models::dp::Utbetaling>,
     path: web::Path<Uuid>,
 ) -> HttpResponse {
     let dryrun = json.0.dryrun.unwrap_or(false);
     let tx: Uuid = path.into_inner();
-    handle_utbetaling(status_pubsub, sim_pubsub, json.0, dryrun, tx).await
+    abetal(&state, &req, json.0, dryrun, Some(tx)).await
 }
 
 #[post("/abetal/ts")]
 pub async fn abetal_ts(
-    status_pubsub: Data<StatusPubSub>,
-    sim_pubsub: Data<SimPubSub>,
+    state: Data<AppState>,
+    req: HttpRequest,
     json: web::Json<models::ts::Utbetaling>,
 ) -> HttpResponse {
     let dryrun = json.0.dryrun.unwrap_or(false);
-    let tx = Uuid::new_v4();
-    handle_utbetaling(status_pubsub, sim_pubsub, json.0, dryrun, tx).await
+    abetal(&state, &req, json.0, dryrun, None).await
+}
+
+#[post("/abetal/ts/{transaction_id}")]
+pub async fn abetal_ts_tx(
+    state: Data<AppState>,
+    req: HttpRequest,
+    json: web::Json<models::ts::Utbetaling>,
+    path: web::Path<Uuid>,
+) -> HttpResponse {
+    let dryrun = json.0.dryrun.unwrap_or(false);
+    let tx: Uuid = path.into_inner();
+    abetal(&state, &req, json.0, dryrun, Some(tx)).await
 }
 
 #[post("/abetal/historisk")]
 pub async fn abetal_historisk(
-    status_pubsub: Data<StatusPubSub>,
-    sim_pubsub: Data<SimPubSub>,
+    state: Data<AppState>,
+    req: HttpRequest,
+    json: web::Json<models::historisk::Utbetaling>,
+) -> HttpResponse {
+    let dryrun = json.0.dryrun.unwrap_or(false);
+    abetal(&state, &req, json.0, dryrun, None).await
+}
+
+#[post("/abetal/historisk/{transaction_id}")]
+pub async fn abetal_historisk_tx(
+    state: Data<AppState>,
+    req: HttpRequest,
     json: web::Json<models::historisk::Utbetaling>,
+    path: web::Path<Uuid>,
 ) -> HttpResponse {
     let dryrun = json.0.dryrun.unwrap_or(false);
-    let tx = Uuid::new_v4();
-    handle_utbetaling(status_pubsub, sim_pubsub, json.0, dryrun, tx).await
+    let tx: Uuid = path.into_inner();
+    abetal(&state, &req, json.0, dryrun, Some(tx)).await
 }
 
 #[post("/abetal/tp")]
 pub async fn abetal_tp(
-    status_pubsub: Data<StatusPubSub>,
-    sim_pubsub: Data<SimPubSub>,
+    state: Data<AppState>,
+    req: HttpRequest,
     json: web::Json<models::tp::Utbetaling>,
 ) -> HttpResponse {
     let dryrun = json.0.dryrun.unwrap_or(false);
-    let tx = Uuid::new_v4();
-    handle_utbetaling(status_pubsub, sim_pubsub, json.0, dryrun, tx).await
+    abetal(&state, &req, json.0, dryrun, None).await
+}
+
+#[post("/abetal/tp/{transaction_id}")]
+pub async fn abetal_tp_tx(
+    state: Data<AppState>,
+    req: HttpRequest,
+    json: web::Json<models::tp::Utbetaling>,
+    path: web::Path<Uuid>,
+) -> HttpResponse {
+    let dryrun = json.0.dryrun.unwrap_or(false);
+    let tx: Uuid = path.into_inner();
+    abetal(&state, &req, json.0, dryrun, Some(tx)).await
+}
+
+async fn abetal<T>(
+    state: &AppState,
+    req: &HttpRequest,
+    utbetaling: T,
+    dryrun: bool,
+    path_transaction_id: Option<Uuid>,
+) -> HttpResponse
+where
+    T: Into<models::Utbetaling> + Clone,
+{
+    let params = match web::Query::<AbetalParams>::from_query(req.query_string()) {
+        Ok(params) => params.into_inner(),
+        Err(e) => return bad_request(e.to_string()),
+    };
+    let transport = params.transport.unwrap_or(state.transports.default);
+
+    let transaction_id = match transaction_id(req, path_transaction_id) {
+        Ok(transaction_id) => transaction_id,
+        Err(msg) => return bad_request(msg.into()),
+    };
+
+    let timeout = match reply_timeout(state, req, params.timeout) {
+        Ok(timeout) => timeout,
+        Err(msg) => return bad_request(msg.into()),
+    };
+
+    if preference(req, "respond-async").is_some() {
+        submit_utbetaling(state, transport, utbetaling, transaction_id, timeout).await
+    } else {
+        handle_utbetaling(state, transport, utbetaling, dryrun, transaction_id, timeout).await
+    }
+}
+
+/// The reply timeout from `?timeout=` or `Prefer: wait=`, capped by the max, or the configured one.
+fn reply_timeout(state: &AppState, req: &HttpRequest, query: Option<u64>) -> Result<Duration, &'static str> {
+    let requested = match query {
+        Some(timeout) => Some(timeout),
+        None => match preference(req, "wait").map(|it| it.parse::<u64>()) {
+            Some(Ok(wait)) => Some(wait),
+            Some(Err(_)) => return Err("Prefer: wait må være et antall sekunder"),
+            None => None,
+        },
+    };
+    if requested == Some(0) {
+        return Err("Timeout må være større enn 0");
+    }
+    Ok(state.timeouts.reply_or(requested))
+}
+
+/// The value of a `Prefer` preference, empty for one without a value.
+fn preference(req: &HttpRequest, name: &str) -> Option<String> {
+    req.headers()
+        .get_all("prefer")
+        .filter_map(|it| it.to_str().ok())
+        .flat_map(|it| it.split(','))
+        .find_map(|pref| {
+            let (key, value) = pref.split_once('=').unwrap_or((pref, ""));
+            key.trim().eq_ignore_ascii_case(name).then(|| value.trim().to_owned())
+        })
+}
+
+fn bad_request(msg: String) -> HttpResponse {
+    let bad_request = Reply {
+        status: Status::Feilet,
+        error: Some(Error {
+            status_code: 400,
+            msg,
+            doc: "https://helved-docs.ansatt.dev.nav.no/v3/doc/".into(),
+        }),
+    };
+    HttpResponse::BadRequest().json(bad_request)
+}
+
+/// The transaction id from the path or the `Idempotency-Key` header, or a new one.
+fn transaction_id(req: &HttpRequest, path: Option<Uuid>) -> Result<Uuid, &'static str> {
+    let key = req
+        .headers()
+        .get("idempotency-key")
+        .map(|it| it.to_str().ok().and_then(|it| Uuid::parse_str(it.trim()).ok()));
+
+    match (path, key) {
+        (_, Some(None)) => Err("Idempotency-Key må være en UUID"),
+        (Some(path), Some(Some(key))) if path != key => Err("Idempotency-Key er ulik transaksjons-id i path"),
+        (Some(path), _) => Ok(path),
+        (None, Some(Some(key))) => Ok(key),
+        (None, None) => Ok(Uuid::new_v4()),
+    }
 }
 
-async fn handle_utbetaling<T>(
-    status_pubsub: web::Data<StatusPubSub>,
-    sim_pubsub: web::Data<SimPubSub>,
+/// Produces the utbetaling and returns right away, replies are collected in
+/// the result store and polled on `/abetal/status/{transaction_id}` until `timeout`.
+pub async fn submit_utbetaling<T>(
+    state: &AppState,
+    transport: TransportKind,
+    utbetaling: T,
+    transaction_id: Uuid,
+    timeout: Duration,
+) -> HttpResponse
+where
+    T: Into<models::Utbetaling>,
+{
+    let Some(_in_flight) = state.shutdown.enter() else {
+        return shutting_down();
+    };
+    let Some(producer) = state.transports.get(transport).cloned() else {
+        return bad_request(format!("Transport {} er ikke satt opp", transport.as_str()));
+    };
+    if !state.results.insert(transaction_id, timeout) {
+        info!("Transaction {transaction_id} is already submitted");
+        return submitted(transaction_id);
+    }
+
+    let utbetaling: models::Utbetaling = utbetaling.into();
+    let fagsystem = utbetaling.fagsystem();
+
+    if let Err(e) = producer.produce(transaction_id, utbetaling).await {
+        state.results.remove(&transaction_id);
+        return reply_response(produce_failed(e));
+    }
+    state.metrics.produced(transport, fagsystem);
+
+    submitted(transaction_id)
+}
+
+fn submitted(transaction_id: Uuid) -> HttpResponse {
+    HttpResponse::Accepted()
+        .insert_header((header::LOCATION, format!("/abetal/status/{transaction_id}")))
+        .json(Submitted { transaction_id })
+}
+
+pub async fn handle_utbetaling<T>(
+    state: &AppState,
+    transport: TransportKind,
     utbetaling: T,
     dryrun: bool,
     transaction_id: Uuid,
+    timeout: Duration,
 ) -> HttpResponse 
 where 
     T: Into<models::Utbetaling> + Clone,
 {
-    let (status_tx, status_rx) = mpsc::channel(100);
-    let (actuator_tx, actuator_rx) = mpsc::channel(100);
-    status_pubsub.lock().await.insert(transaction_id, (status_tx, actuator_rx));
+    let Some(_in_flight) = state.shutdown.enter() else {
+        return shutting_down();
+    };
+    let Some(producer) = state.transports.get(transport).cloned() else {
+        return bad_request(format!("Transport {} er ikke satt opp", transport.as_str()));
+    };
 
-    let mut sim_rx_opt = None;
-    if dryrun {
-        let (sim_tx, sim_rx) = mpsc::channel(100);
-        sim_pubsub.lock().await.insert(transaction_id, sim_tx);
-        sim_rx_opt = Some(sim_rx)
-    }
+    let first = state.results.insert(transaction_id, timeout);
+    let subscribed = state.subscribe(transaction_id, dryrun);
 
-    kafka::produce_utbetaling(transaction_id, utbetaling.into()).await;
+    let utbetaling: models::Utbetaling = utbetaling.into();
+    let fagsystem = utbetaling.fagsystem();
+    let started = Instant::now();
+
+    if first {
+        if let Err(e) = producer.produce(transaction_id, utbetaling).await {
+            info!("Failed to produce {transaction_id}: {e}");
+            let reply = produce_failed(e);
+            // Requests waiting on the same transaction id get the failure too, not a timeout.
+            state.pending.status(&transaction_id, reply.clone());
+            drop(subscribed);
+            state.results.remove(&transaction_id);
+            state.metrics.latency(transport, fagsystem, dryrun, Outcome::Feilet, started.elapsed());
+            return reply_response(reply);
+        }
+        state.metrics.produced(transport, fagsystem);
+    } else {
+        info!("Transaction {transaction_id} is already submitted, waiting for its result");
+    }
+    let acked = Instant::now();
 
     let mut handlers = Vec::new();
 
-    if let Some(sim_rx) = sim_rx_opt {
-        handlers.push(actix_web::rt::spawn(simulering_handler(sim_rx)));
+    // Dryruns are answered with a simulering alone, there is no MOTTATT to wait for.
+    let mottatt = (!dryrun).then(|| state.timeouts.mottatt().min(timeout));
+    if let Some(sim_rx) = subscribed.sim_rx.clone() {
+        handlers.push(actix_web::rt::spawn(simulering_handler(sim_rx, timeout)));
     }
 
-    handlers.push(actix_web::rt::spawn(status_handler(transaction_id, status_rx, actuator_tx)));
+    handlers.push(actix_web::rt::spawn(status_handler(subscribed.status_rx.clone(), mottatt, timeout)));
 
     let (first_done, _idx, rest) = select_all(handlers).await;
 
@@ -127,66 +528,80 @@ where
         h.abort();
     }
 
-    first_done.unwrap_or_else(|_| HttpResponse::InternalServerError().finish())
+    let mut res = first_done.unwrap_or_else(|_| HttpResponse::InternalServerError().finish());
+    if res.status() == StatusCode::REQUEST_TIMEOUT {
+        state.results.gave_up(&transaction_id);
+    }
+    if !first {
+        return res;
+    }
+
+    let stages = stages(started, acked, &subscribed.status_rx.borrow());
+    for (stage, elapsed) in &stages {
+        state.metrics.stage(transport, fagsystem, stage, *elapsed);
+    }
+    if let Ok(value) = HeaderValue::from_str(&server_timing(&stages)) {
+        res.headers_mut().insert(HeaderName::from_static("server-timing"), value);
+    }
+
+    let outcome = match res.status() {
+        StatusCode::REQUEST_TIMEOUT => Outcome::Timeout,
+        status if status.is_success() => Outcome::Ok,
+        _ => Outcome::Feilet,
+    };
+    state.metrics.latency(transport, fagsystem, dryrun, outcome, started.elapsed());
+    res
+}
+
+fn shutting_down() -> HttpResponse {
+    let shutting_down = Reply {
+        status: Status::Feilet,
+        error: Some(Error {
+            status_code: 503,
+            msg: "Tjenesten stopper, prøv igjen".into(),
+            doc: "https://helved-docs.ansatt.dev.nav.no/v3/doc/".into(),
+        }),
+    };
+    HttpResponse::ServiceUnavailable().json(shutting_down)
+}
+
+fn produce_failed(e: ProduceError) -> Reply {
+    Reply {
+        status: Status::Feilet,
+        error: Some(Error {
+            status_code: e.status_code(),
+            msg: e.to_string(),
+            doc: "https://helved-docs.ansatt.dev.nav.no/v3/doc/".into(),
+        }),
+    }
 }
 
-async fn simulering_handler(mut sim_rx: mpsc::Receiver<Simulering>) -> HttpResponse {
-    let timeout_duration = Duration::from_secs(30);
-    let result = time::timeout(timeout_duration, sim_rx.recv()).await;
+async fn simulering_handler(mut sim_rx: watch::Receiver<Option<Simulering>>, timeout_duration: Duration) -> HttpResponse {
+    let result = time::timeout(timeout_duration, sim_rx.wait_for(Option::is_some)).await;
     match result {
-        Ok(Some(sim)) => HttpResponse::Ok().json(sim),
-        _ => {
-            let timeout_error = Reply {
-                status: Status::Feilet,
-                error: Some(Error {
-                    status_code: 408,
-                    msg: "Fikk ingen response på simulering innen 30 sec".into(),
-                    doc: "https://helved-docs.ansatt.dev.nav.no/v3/doc/".into(),
-                }),
-            };
-            HttpResponse::RequestTimeout().json(timeout_error)
-        }
+        Ok(Ok(sim)) => HttpResponse::Ok().json(sim.as_ref()),
+        _ => timed_out(format!("Fikk ingen response på simulering innen {} sec", timeout_duration.as_secs())),
     }
 }
 
+/// Gives up without a first status within `mottatt`, which means the pipeline is stuck,
+/// or without a final status within `timeout`, which means Oppdrag is slow.
 async fn status_handler(
-    uid: Uuid,
-    status_rx: mpsc::Receiver<Reply>,
-    actuator_tx: mpsc::Sender<Uuid>,
+    mut status_rx: watch::Receiver<Timeline>,
+    mottatt: Option<Duration>,
+    timeout_duration: Duration,
 ) -> HttpResponse {
-    let timeout_duration = Duration::from_secs(30);
-    let monitor_future = monitor_replies(status_rx);
-    let result = time::timeout(timeout_duration, monitor_future).await;
-
-    let _ = actuator_tx.send(uid).await; // unsubscribe uid
-
-    match result {
-        Ok(Some(reply)) => {
-            match reply.status {
-                Status::Ok => HttpResponse::Ok().json(reply),
-                Status::Feilet => {
-                    match reply.error {
-                        None => HttpResponse::InternalServerError().json(reply),
-                        Some(ref error) => {
-                            let status_code = StatusCode::from_u16(error.status_code).unwrap_or(StatusCode::BAD_REQUEST);
-                            HttpResponse::build(status_code).json(reply)
-                        }
-                    }
-                }
-                _ => HttpResponse::InternalServerError().finish()
-            }
-        }
-        Err(_) => {
-            let timeout_error = Reply {
-                status: Status::Feilet,
-                error: Some(Error {
-                    status_code: 408,
-                    msg: "Fikk ingen endelig status innen 30 sec".into(),
-                    doc: "https://helved-docs.ansatt.dev.nav.no/v3/doc/".into(),
-                }),
-            };
-            HttpResponse::RequestTimeout().json(timeout_error)
+    let deadline = Instant::now() + timeout_duration;
+    if let Some(mottatt) = mottatt {
+        let first = time::timeout(mottatt, status_rx.wait_for(|it| !it.replies.is_empty())).await.map(|_| ());
+        if first.is_err() {
+            return timed_out(format!("Fikk ingen MOTTATT innen {} sec", mottatt.as_secs()));
         }
+    }
+
+    match time::timeout_at(deadline, monitor_replies(status_rx)).await {
+        Ok(Some(reply)) => reply_response(reply),
+        Err(_) => timed_out(format!("Fikk ingen endelig status innen {} sec", timeout_duration.as_secs())),
         Ok(None) => {
             let closed_error = Reply {
                 status: Status::Feilet,
@@ -201,23 +616,438 @@ async fn status_handler(
     }
 }
 
-async fn monitor_replies(mut status_rx: mpsc::Receiver<Reply>) -> Option<Reply> {
-    let mut last_reply: Option<Reply> = None;
-    loop {
-        match status_rx.recv().await {
-            Some(res) => {
-                match res.status {
-                    Status::Ok | Status::Feilet => {
-                        return Some(res);
-                    }
-                    _ => {
-                        last_reply = Some(res);
-                        continue;
-                    }
+fn timed_out(msg: String) -> HttpResponse {
+    let timeout_error = Reply {
+        status: Status::Feilet,
+        error: Some(Error {
+            status_code: 408,
+            msg,
+            doc: "https://helved-docs.ansatt.dev.nav.no/v3/doc/".into(),
+        }),
+    };
+    HttpResponse::RequestTimeout().json(timeout_error)
+}
+
+fn reply_response(reply: Reply) -> HttpResponse {
+    match reply.status {
+        Status::Ok => HttpResponse::Ok().json(reply),
+        Status::Feilet => {
+            match reply.error {
+                None => HttpResponse::InternalServerError().json(reply),
+                Some(ref error) => {
+                    let status_code = StatusCode::from_u16(error.status_code).unwrap_or(StatusCode::BAD_REQUEST);
+                    HttpResponse::build(status_code).json(reply)
                 }
             }
-            None => return last_reply
         }
+        _ => HttpResponse::InternalServerError().finish()
     }
 }
 
+async fn monitor_replies(mut status_rx: watch::Receiver<Timeline>) -> Option<Reply> {
+    loop {
+        if let Some(reply) = status_rx.borrow_and_update().last()
+            && reply.status.is_final()
+        {
+            return Some(reply.clone());
+        }
+        if status_rx.changed().await.is_err() {
+            return status_rx.borrow().last().cloned();
+        }
+    }
+}
+
+/// Time spent reaching each stage, measured from the stage before it:
+/// the produce ack first, then every status reply in the order it arrived.
+fn stages(started: Instant, acked: Instant, timeline: &Timeline) -> Vec<(&'static str, Duration)> {
+    let mut stages = vec![("produce", acked - started)];
+    let mut previous = acked;
+    for (at, reply) in &timeline.replies {
+        stages.push((reply.status.as_str(), at.saturating_duration_since(previous)));
+        previous = *at;
+    }
+    stages
+}
+
+fn server_timing(stages: &[(&str, Duration)]) -> String {
+    stages
+        .iter()
+        .map(|(stage, elapsed)| format!("{stage};dur={:.1}", elapsed.as_secs_f64() * 1000.0))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::App;
+    use actix_web::test::{TestRequest, call_service, init_service, read_body, read_body_json};
+
+    use crate::loadgen;
+    use crate::mock::{Latency, MockUtsjekk};
+    use crate::transport::{InMemory, Transport};
+    use futures::future::BoxFuture;
+
+    /// Counts what is produced, and leaves the replies to the mock.
+    struct Counting {
+        inner: InMemory,
+        produced: std::sync::atomic::AtomicUsize,
+    }
+
+    impl Transport for Counting {
+        fn produce(&self, uid: Uuid, utbetaling: models::Utbetaling) -> BoxFuture<'_, Result<(), ProduceError>> {
+            self.produced.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            self.inner.produce(uid, utbetaling)
+        }
+
+        fn subscribe(&self, inbox: Inbox) {
+            self.inner.subscribe(inbox)
+        }
+    }
+
+    /// Refuses every utbetaling, like a broker that never acks.
+    struct Refusing;
+
+    impl Transport for Refusing {
+        fn produce(&self, _: Uuid, _: models::Utbetaling) -> BoxFuture<'_, Result<(), ProduceError>> {
+            Box::pin(async { Err(ProduceError::Delivery("Local: Message timed out".into())) })
+        }
+
+        fn subscribe(&self, _: Inbox) {}
+    }
+
+    fn state(mock: Option<MockUtsjekk>) -> AppState {
+        let shared_metrics = Arc::new(Metrics::default());
+        let state = AppState {
+            transports: Transports::new(TransportKind::Memory)
+                .with(TransportKind::Memory, Arc::new(InMemory::new(mock))),
+            pending: Arc::new(Registry::new(Duration::from_secs(600), shared_metrics.clone())),
+            results: Arc::new(ResultStore::new(100, Duration::from_secs(600))),
+            metrics: shared_metrics,
+            timeouts: Timeouts::default(),
+            batch: BatchConfig::default(),
+            shutdown: Arc::new(Shutdown::default()),
+        };
+        state.transports.subscribe(&state.inbox());
+        state
+    }
+
+    fn mock(failure_rate: f64) -> MockUtsjekk {
+        MockUtsjekk {
+            mottatt: Latency::Fixed(1),
+            hos_oppdrag: Latency::Fixed(1),
+            kvittering: Latency::Fixed(1),
+            simulering: Latency::Fixed(1),
+            failure_rate,
+        }
+    }
+
+    async fn post_aap(state: AppState, dryrun: bool) -> actix_web::dev::ServiceResponse {
+        post_aap_to(state, dryrun, "/abetal/aap").await
+    }
+
+    async fn post_aap_to(state: AppState, dryrun: bool, uri: &str) -> actix_web::dev::ServiceResponse {
+        let models::Utbetaling::Aap(aap) = loadgen::generate(models::Fagsystem::Aap, dryrun) else {
+            unreachable!()
+        };
+        let app = init_service(App::new().app_data(Data::new(state)).service(abetal_aap)).await;
+        let req = TestRequest::post().uri(uri).set_json(aap).to_request();
+        call_service(&app, req).await
+    }
+
+    #[actix_web::test]
+    async fn test_abetal_until_final_status() {
+        let state = state(Some(mock(0.0)));
+        let res = post_aap(state.clone(), false).await;
+
+        assert_eq!(res.status(), StatusCode::OK);
+        let server_timing = res.headers().get("server-timing").unwrap().to_str().unwrap().to_owned();
+        assert!(server_timing.starts_with("produce;dur="), "{server_timing}");
+        assert!(server_timing.contains("mottatt;dur=") && server_timing.contains("ok;dur="), "{server_timing}");
+        let reply: Reply = read_body_json(res).await;
+        assert_eq!(reply.status, Status::Ok);
+        assert!(state.pending.is_empty(), "Transaction should be unsubscribed when answered");
+    }
+
+    #[actix_web::test]
+    async fn test_abetal_feilet_uses_error_status_code() {
+        let res = post_aap(state(Some(mock(1.0))), false).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let reply: Reply = read_body_json(res).await;
+        assert_eq!(reply.status, Status::Feilet);
+    }
+
+    #[actix_web::test]
+    async fn test_abetal_dryrun_returns_simulering() {
+        let res = post_aap(state(Some(mock(0.0))), true).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let _: Simulering = read_body_json(res).await;
+    }
+
+    #[actix_web::test]
+    async fn test_ready_when_every_transport_is_ready() {
+        let app = init_service(
+            App::new().app_data(Data::new(state(None))).service(ready).service(health_details),
+        )
+        .await;
+        let res = call_service(&app, TestRequest::get().uri("/ready").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = call_service(&app, TestRequest::get().uri("/health/details").to_request()).await;
+        let details: serde_json::Value = read_body_json(res).await;
+        assert_eq!(details["ready"], true);
+        assert!(details["transports"].get("memory").is_some(), "{details}");
+    }
+
+    #[actix_web::test]
+    async fn test_shutdown_turns_new_utbetalinger_away() {
+        let state = state(Some(mock(0.0)));
+        state.shutdown.begin();
+
+        let res = post_aap(state.clone(), false).await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(state.pending.is_empty(), "Nothing should be subscribed while shutting down");
+
+        let app = init_service(App::new().app_data(Data::new(state)).service(ready)).await;
+        let res = call_service(&app, TestRequest::get().uri("/ready").to_request()).await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[actix_web::test]
+    async fn test_abetal_delivery_failure_answers_at_once() {
+        let mut state = state(None);
+        state.transports = Transports::new(TransportKind::Memory).with(TransportKind::Memory, Arc::new(Refusing));
+        let res = post_aap(state.clone(), true).await;
+
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+        let reply: Reply = read_body_json(res).await;
+        assert!(reply.error.unwrap().msg.contains("Message timed out"));
+        assert!(state.pending.is_empty(), "Failed transaction should be unsubscribed");
+    }
+
+    #[actix_web::test]
+    async fn test_repeated_transaction_id_attaches_instead_of_producing_again() {
+        time::pause();
+        let counting = Arc::new(Counting { inner: InMemory::new(Some(mock(0.0))), produced: Default::default() });
+        let mut state = state(None);
+        state.transports = Transports::new(TransportKind::Memory).with(TransportKind::Memory, counting.clone());
+        state.transports.subscribe(&state.inbox());
+        let app = init_service(App::new().app_data(Data::new(state)).service(abetal_aap).service(abetal_aap_tx)).await;
+
+        let uid = Uuid::new_v4();
+        let models::Utbetaling::Aap(aap) = loadgen::generate(models::Fagsystem::Aap, false) else {
+            unreachable!()
+        };
+        let by_path = || TestRequest::post().uri(&format!("/abetal/aap/{uid}")).set_json(&aap).to_request();
+        let by_key = || {
+            let req = TestRequest::post().uri("/abetal/aap").insert_header(("idempotency-key", uid.to_string()));
+            req.set_json(&aap).to_request()
+        };
+
+        let (first, second) = futures::join!(call_service(&app, by_path()), call_service(&app, by_key()));
+        let after = call_service(&app, by_path()).await;
+
+        let mut replies = Vec::new();
+        for res in [first, second, after] {
+            assert_eq!(res.status(), StatusCode::OK);
+            replies.push(read_body_json::<serde_json::Value, _>(res).await);
+        }
+        assert!(replies.iter().all(|it| *it == replies[0]), "{replies:?}");
+        assert_eq!(counting.produced.load(std::sync::atomic::Ordering::SeqCst), 1, "Utbetaling should be produced once");
+
+        let invalid = TestRequest::post().uri("/abetal/aap").insert_header(("idempotency-key", "not-a-uuid"));
+        let res = call_service(&app, invalid.set_json(&aap).to_request()).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        let mismatch = TestRequest::post()
+            .uri(&format!("/abetal/aap/{uid}"))
+            .insert_header(("idempotency-key", Uuid::new_v4().to_string()));
+        let res = call_service(&app, mismatch.set_json(&aap).to_request()).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_subscribing_finds_a_reply_however_it_lands() {
+        let state = state(None);
+        let ok = Reply { status: Status::Ok, error: None };
+
+        let stored = Uuid::new_v4();
+        state.results.insert(stored, state.timeouts.reply());
+        state.inbox().status(stored, ok.clone());
+        let subscribed = state.subscribe(stored, false);
+        assert_eq!(subscribed.status_rx.borrow().last(), Some(&ok), "Reply nobody was registered for should be caught up");
+
+        // Registered, then the reply lands before the store is read, so it is both routed and stored.
+        let between = Uuid::new_v4();
+        state.results.insert(between, state.timeouts.reply());
+        let (_rx, _sim_rx, _registered) = state.pending.subscribe(between);
+        state.inbox().status(between, ok.clone());
+        let subscribed = state.subscribe(between, false);
+        assert_eq!(subscribed.status_rx.borrow().replies.len(), 1, "Reply should be in the timeline once");
+    }
+
+    #[actix_web::test]
+    async fn test_events_follow_the_transaction_deadline() {
+        time::pause();
+        let state = state(None);
+        let (done, waiting) = (Uuid::new_v4(), Uuid::new_v4());
+        state.results.insert(done, state.timeouts.reply());
+        state.inbox().status(done, Reply { status: Status::Ok, error: None });
+        state.results.insert(waiting, Duration::from_secs(90));
+        let app = init_service(App::new().app_data(Data::new(state)).service(abetal_events)).await;
+
+        let res = call_service(&app, TestRequest::get().uri(&format!("/abetal/{done}/events")).to_request()).await;
+        let body = String::from_utf8(read_body(res).await.to_vec()).unwrap();
+        assert!(body.starts_with("event: status\ndata: {\"status\":\"OK\""), "{body}");
+
+        time::advance(Duration::from_secs(60)).await;
+        let res = call_service(&app, TestRequest::get().uri(&format!("/abetal/{waiting}/events")).to_request()).await;
+        let body = String::from_utf8(read_body(res).await.to_vec()).unwrap();
+        assert!(body.contains("Fikk ingen endelig status innen 90 sec"), "{body}");
+    }
+
+    #[actix_web::test]
+    async fn test_abetal_transport_not_set_up() {
+        let res = post_aap_to(state(Some(mock(0.0))), false, "/abetal/aap?transport=rest").await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn test_abetal_times_out_without_reply() {
+        time::pause();
+        let state = state(None);
+        let res = post_aap(state.clone(), false).await;
+
+        assert_eq!(res.status(), StatusCode::REQUEST_TIMEOUT);
+        assert!(state.pending.is_empty(), "Timed out transaction should be unsubscribed");
+    }
+
+    #[actix_web::test]
+    async fn test_batch_as_json_array_and_jsonl() {
+        let item = |fagsystem: models::Fagsystem| {
+            let utbetaling: serde_json::Value = serde_json::from_str(&loadgen::generate(fagsystem, false).payload()).unwrap();
+            serde_json::json!({ "fagsystem": fagsystem, "utbetaling": utbetaling })
+        };
+        let app = init_service(App::new().app_data(Data::new(state(Some(mock(0.0))))).service(abetal_batch)).await;
+
+        let array = serde_json::json!([item(models::Fagsystem::Aap), item(models::Fagsystem::Dp), { "fagsystem": "ukjent" }]);
+        let req = TestRequest::post().uri("/abetal/batch?parallelism=2").set_payload(array.to_string());
+        let res: serde_json::Value = read_body_json(call_service(&app, req.to_request()).await).await;
+        assert_eq!(res["total"], 3);
+        assert_eq!(res["statusCodes"], serde_json::json!({ "200": 2, "400": 1 }));
+        assert_eq!(res["items"][1]["fagsystem"], "dp");
+        assert_eq!(res["items"][1]["status"], "OK");
+        assert!(res["items"][1]["transactionId"].is_string());
+
+        let jsonl = format!("{}\n\n{}\n", item(models::Fagsystem::Ts), item(models::Fagsystem::Historisk));
+        let req = TestRequest::post().uri("/abetal/batch").set_payload(jsonl);
+        let res: serde_json::Value = read_body_json(call_service(&app, req.to_request()).await).await;
+        assert_eq!(res["statusCodes"], serde_json::json!({ "200": 2 }));
+        assert_eq!(res["parallelism"], BatchConfig::default().parallelism);
+    }
+
+    #[actix_web::test]
+    async fn test_stuck_pipeline_and_slow_oppdrag_time_out_separately() {
+        time::pause();
+        let res = post_aap_to(state(None), false, "/abetal/aap?timeout=5").await;
+        assert_eq!(res.status(), StatusCode::REQUEST_TIMEOUT);
+        let reply: Reply = read_body_json(res).await;
+        assert_eq!(reply.error.unwrap().msg, "Fikk ingen MOTTATT innen 5 sec");
+
+        let slow_oppdrag = MockUtsjekk { kvittering: Latency::Fixed(600_000), ..mock(0.0) };
+        let models::Utbetaling::Aap(aap) = loadgen::generate(models::Fagsystem::Aap, false) else {
+            unreachable!()
+        };
+        let app = init_service(App::new().app_data(Data::new(state(Some(slow_oppdrag)))).service(abetal_aap)).await;
+        let req = TestRequest::post().uri("/abetal/aap").insert_header(("prefer", "wait=300")).set_json(aap);
+        let res = call_service(&app, req.to_request()).await;
+        assert_eq!(res.status(), StatusCode::REQUEST_TIMEOUT);
+        let reply: Reply = read_body_json(res).await;
+        assert_eq!(reply.error.unwrap().msg, "Fikk ingen endelig status innen 120 sec", "Wait should be capped");
+    }
+
+    #[actix_web::test]
+    async fn test_late_measured_against_the_timeout_the_request_used() {
+        time::pause();
+        let state = state(None);
+        let uid = Uuid::new_v4();
+        let models::Utbetaling::Aap(aap) = loadgen::generate(models::Fagsystem::Aap, false) else {
+            unreachable!()
+        };
+        let app = init_service(App::new().app_data(Data::new(state.clone())).service(abetal_aap_tx)).await;
+        let req = TestRequest::post().uri(&format!("/abetal/aap/{uid}?timeout=5")).set_json(aap);
+        assert_eq!(call_service(&app, req.to_request()).await.status(), StatusCode::REQUEST_TIMEOUT);
+
+        time::advance(Duration::from_secs(15)).await;
+        state.inbox().status(uid, Reply { status: Status::Ok, error: None });
+
+        let late = state.results.late_replies(&uid).unwrap();
+        assert_eq!(late.len(), 1, "Reply after the request's own timeout should be late");
+    }
+
+    #[actix_web::test]
+    async fn test_async_submit_keeps_the_requested_timeout() {
+        time::pause();
+        let models::Utbetaling::Aap(aap) = loadgen::generate(models::Fagsystem::Aap, false) else {
+            unreachable!()
+        };
+        let app = init_service(App::new().app_data(Data::new(state(None))).service(abetal_aap).service(abetal_status)).await;
+        let req = TestRequest::post().uri("/abetal/aap?timeout=90").insert_header(("prefer", "respond-async"));
+        let submitted: serde_json::Value = read_body_json(call_service(&app, req.set_json(aap).to_request()).await).await;
+        let status = format!("/abetal/status/{}", submitted["transactionId"].as_str().unwrap());
+
+        time::advance(Duration::from_secs(60)).await;
+        let res = call_service(&app, TestRequest::get().uri(&status).to_request()).await;
+        assert_eq!(res.status(), StatusCode::ACCEPTED, "Past the default timeout, still within the requested one");
+
+        time::advance(Duration::from_secs(31)).await;
+        let res = call_service(&app, TestRequest::get().uri(&status).to_request()).await;
+        assert_eq!(res.status(), StatusCode::REQUEST_TIMEOUT);
+        let reply: Reply = read_body_json(res).await;
+        assert_eq!(reply.error.unwrap().msg, "Fikk ingen endelig status innen 90 sec");
+    }
+
+    #[actix_web::test]
+    async fn test_late_and_orphan_replies_queryable_by_transaction_id() {
+        time::pause();
+        let state = state(None);
+        let (uid, unknown) = (Uuid::new_v4(), Uuid::new_v4());
+        state.results.insert(uid, state.timeouts.reply());
+        time::advance(state.timeouts.reply() + Duration::from_secs(5)).await;
+        state.inbox().status(uid, Reply { status: Status::Ok, error: None });
+        state.inbox().status(unknown, Reply { status: Status::Ok, error: None });
+
+        let app = init_service(App::new().app_data(Data::new(state)).service(abetal_late)).await;
+        let res = call_service(&app, TestRequest::get().uri(&format!("/abetal/late/{uid}")).to_request()).await;
+        let late: serde_json::Value = read_body_json(res).await;
+        assert_eq!(late[0]["status"], "OK");
+        assert_eq!(late[0]["orphan"], false);
+        assert!(late[0]["delayMs"].as_u64().unwrap() >= 35_000, "{late}");
+
+        let res = call_service(&app, TestRequest::get().uri(&format!("/abetal/late/{unknown}")).to_request()).await;
+        let orphan: serde_json::Value = read_body_json(res).await;
+        assert_eq!(orphan[0]["orphan"], true);
+        assert!(orphan[0]["delayMs"].is_null());
+
+        let res = call_service(&app, TestRequest::get().uri(&format!("/abetal/late/{}", Uuid::new_v4())).to_request()).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_stages_measured_from_previous_stage() {
+        let started = Instant::now();
+        let acked = started + Duration::from_millis(10);
+        let mut timeline = Timeline::default();
+        for (ms, status) in [(40, Status::Mottatt), (100, Status::HosOppdrag), (1000, Status::Ok)] {
+            let reply = Reply { status, error: None };
+            timeline.replies.push((started + Duration::from_millis(ms), reply));
+        }
+
+        let stages = stages(started, acked, &timeline);
+
+        assert_eq!(
+            server_timing(&stages),
+            "produce;dur=10.0, mottatt;dur=30.0, hos_oppdrag;dur=60.0, ok;dur=900.0"
+        );
+    }
+}
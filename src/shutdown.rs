@@ -0,0 +1,101 @@
+use log::info;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use tokio::sync::Notify;
+use tokio::time::{self, Duration, Instant};
+
+/// Keeps count of the utbetalinger in flight, and turns new ones away once shutdown has begun.
+#[derive(Default)]
+pub struct Shutdown {
+    draining: AtomicBool,
+    in_flight: AtomicUsize,
+    idle: Notify,
+}
+
+/// One utbetaling in flight, until dropped.
+pub struct InFlight(Arc<Shutdown>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if self.0.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+impl Shutdown {
+    /// None once shutdown has begun.
+    pub fn enter(self: &Arc<Self>) -> Option<InFlight> {
+        if self.is_draining() {
+            return None;
+        }
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        Some(InFlight(self.clone()))
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    pub fn begin(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+    }
+
+    /// Waits for the utbetalinger in flight, false when some are left after the grace period.
+    pub async fn drain(&self, grace: Duration) -> bool {
+        let deadline = Instant::now() + grace;
+        loop {
+            let idle = self.idle.notified();
+            tokio::pin!(idle);
+            idle.as_mut().enable();
+
+            let in_flight = self.in_flight.load(Ordering::SeqCst);
+            if in_flight == 0 {
+                return true;
+            }
+            info!("Waiting for {in_flight} utbetalinger in flight");
+            if time::timeout_at(deadline, idle).await.is_err() {
+                return false;
+            }
+        }
+    }
+}
+
+/// Resolves on SIGTERM or ctrl-c.
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use actix_web::rt::signal::unix::{SignalKind, signal};
+        let mut terminate = signal(SignalKind::terminate()).expect("listen for SIGTERM");
+        tokio::select! {
+            _ = terminate.recv() => info!("Received SIGTERM"),
+            _ = actix_web::rt::signal::ctrl_c() => info!("Received ctrl-c"),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = actix_web::rt::signal::ctrl_c().await;
+        info!("Received ctrl-c");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_drain_waits_for_in_flight() {
+        let shutdown = Arc::new(Shutdown::default());
+        let in_flight = shutdown.enter().unwrap();
+
+        shutdown.begin();
+        assert!(shutdown.enter().is_none(), "New utbetalinger should be turned away");
+        assert!(!shutdown.drain(Duration::from_millis(10)).await, "Grace period should run out");
+
+        actix_web::rt::spawn(async move {
+            time::sleep(Duration::from_millis(10)).await;
+            drop(in_flight);
+        });
+        assert!(shutdown.drain(Duration::from_secs(5)).await);
+    }
+}
@@ -0,0 +1,207 @@
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use dashmap::mapref::entry::Entry as DashEntry;
+use log::info;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::time::{self, Duration, Instant};
+use uuid::Uuid;
+
+use crate::metrics::ReplyKind;
+use crate::models::dryrun::Simulering;
+use crate::models::status::{Reply, Status};
+
+/// Latest reply per transaction, used for polling and to recognise repeated submits.
+/// Entries live for the TTL after submit, the oldest is evicted when full.
+/// Also keeps the replies nobody waited for, so a timeout can be reconciled later.
+pub struct ResultStore {
+    entries: DashMap<Uuid, Entry>,
+    /// uids oldest first, some may already be removed or swept
+    order: Mutex<VecDeque<Uuid>>,
+    orphans: Mutex<VecDeque<(Uuid, LateReply)>>,
+    capacity: usize,
+    ttl: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub submitted: Instant,
+    /// when the request gave up, or will, replies after it with nobody waiting are late
+    pub deadline: Instant,
+    pub reply: Option<Reply>,
+    pub simulering: Option<Simulering>,
+    pub late: Vec<LateReply>,
+}
+
+/// When a reply arrived, relative to its transaction.
+pub struct Arrival {
+    pub since_submit: Duration,
+    pub after_deadline: bool,
+}
+
+/// A reply that arrived after the request gave up on it, or for a transaction we don't know.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LateReply {
+    #[serde(rename = "type")]
+    pub kind: ReplyKind,
+    pub status: Option<Status>,
+    /// since the utbetaling was produced, unknown for orphans
+    pub delay_ms: Option<u64>,
+    pub orphan: bool,
+    pub arrived: DateTime<Utc>,
+}
+
+impl ResultStore {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            entries: DashMap::new(),
+            order: Mutex::new(VecDeque::new()),
+            orphans: Mutex::new(VecDeque::new()),
+            capacity,
+            ttl,
+        }
+    }
+
+    /// Returns false when the transaction is already in the store.
+    pub fn insert(&self, uid: Uuid, timeout: Duration) -> bool {
+        if self.entries.contains_key(&uid) {
+            return false;
+        }
+        if self.entries.len() >= self.capacity {
+            self.sweep();
+        }
+        let mut order = self.order.lock().unwrap();
+        while self.entries.len() >= self.capacity
+            && let Some(oldest) = order.pop_front()
+        {
+            self.entries.remove(&oldest);
+        }
+        match self.entries.entry(uid) {
+            DashEntry::Occupied(_) => false,
+            DashEntry::Vacant(entry) => {
+                let submitted = Instant::now();
+                entry.insert(Entry { submitted, deadline: submitted + timeout, reply: None, simulering: None, late: Vec::new() });
+                order.push_back(uid);
+                true
+            }
+        }
+    }
+
+    /// Rare, only when the utbetaling could not be sent, so the scan for its place in the order is fine.
+    pub fn remove(&self, uid: &Uuid) {
+        self.entries.remove(uid);
+        self.order.lock().unwrap().retain(|it| it != uid);
+    }
+
+    pub fn get(&self, uid: &Uuid) -> Option<Entry> {
+        self.entries.get(uid).map(|it| it.clone())
+    }
+
+    /// The request stopped waiting, which is before its timeout when MOTTATT never came.
+    pub fn gave_up(&self, uid: &Uuid) {
+        if let Some(mut entry) = self.entries.get_mut(uid) {
+            entry.deadline = entry.deadline.min(Instant::now());
+        }
+    }
+
+    /// None when the transaction is not in the store.
+    pub fn reply(&self, uid: &Uuid, reply: &Reply) -> Option<Arrival> {
+        let mut entry = self.entries.get_mut(uid)?;
+        entry.reply = Some(reply.clone());
+        Some(entry.arrival())
+    }
+
+    /// None when the transaction is not in the store.
+    pub fn simulering(&self, uid: &Uuid, simulering: &Simulering) -> Option<Arrival> {
+        let mut entry = self.entries.get_mut(uid)?;
+        entry.simulering = Some(simulering.clone());
+        Some(entry.arrival())
+    }
+
+    /// Keeps the late reply on the transaction, or with the newest orphans when it is not in the store.
+    pub fn late(&self, uid: Uuid, late: LateReply) {
+        if let Some(mut entry) = self.entries.get_mut(&uid) {
+            entry.late.push(late);
+            return;
+        }
+        let mut orphans = self.orphans.lock().unwrap();
+        if orphans.len() >= self.capacity {
+            orphans.pop_front();
+        }
+        orphans.push_back((uid, late));
+    }
+
+    /// Late and orphan replies for the transaction, None when we know nothing about it.
+    pub fn late_replies(&self, uid: &Uuid) -> Option<Vec<LateReply>> {
+        let entry = self.entries.get(uid).map(|it| it.late.clone());
+        let orphans: Vec<_> = self
+            .orphans
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(it, _)| it == uid)
+            .map(|(_, late)| late.clone())
+            .collect();
+        match entry {
+            Some(mut late) => {
+                late.extend(orphans);
+                Some(late)
+            }
+            None if orphans.is_empty() => None,
+            None => Some(orphans),
+        }
+    }
+
+    fn sweep(&self) {
+        let before = self.entries.len();
+        self.entries.retain(|_, entry| entry.submitted.elapsed() < self.ttl);
+        let removed = before - self.entries.len();
+        if removed > 0 {
+            info!("Removed {removed} expired results");
+        }
+        self.order.lock().unwrap().retain(|it| self.entries.contains_key(it));
+        let now = Utc::now();
+        let ttl = chrono::Duration::from_std(self.ttl).unwrap_or(chrono::TimeDelta::MAX);
+        self.orphans.lock().unwrap().retain(|(_, it)| now - it.arrived < ttl);
+    }
+}
+
+impl Entry {
+    fn arrival(&self) -> Arrival {
+        Arrival { since_submit: self.submitted.elapsed(), after_deadline: Instant::now() > self.deadline }
+    }
+}
+
+pub async fn sweeper(store: Arc<ResultStore>) {
+    let mut interval = time::interval(store.ttl.min(Duration::from_secs(30)));
+    loop {
+        interval.tick().await;
+        store.sweep();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_oldest_evicted_when_full() {
+        let store = ResultStore::new(2, Duration::from_secs(600));
+        let (first, second, third) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        assert!(store.insert(first, Duration::from_secs(30)));
+        assert!(!store.insert(first, Duration::from_secs(30)), "Repeated insert should be recognised");
+        store.insert(second, Duration::from_secs(30));
+        store.insert(third, Duration::from_secs(30));
+        assert!(store.get(&first).is_none(), "Oldest result should be evicted");
+        assert!(store.get(&second).is_some());
+        assert!(store.get(&third).is_some());
+
+        store.remove(&second);
+        store.insert(second, Duration::from_secs(30));
+        store.insert(first, Duration::from_secs(30));
+        assert!(store.get(&third).is_none(), "Evicted in insertion order");
+        assert!(store.get(&second).is_some(), "A removed and inserted again result should count as new");
+    }
+}
@@ -0,0 +1,242 @@
+use chrono::Utc;
+use futures::future::BoxFuture;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::azure::TokenError;
+use crate::metrics::{Metrics, ReplyKind};
+use crate::mock::{MockReply, MockUtsjekk};
+use crate::models::Utbetaling;
+use crate::models::dryrun::Simulering;
+use crate::models::status::{Reply, Status};
+use crate::pubsub::Registry;
+use crate::store::{Arrival, LateReply, ResultStore};
+
+/// How utbetalinger reach utsjekk and how its replies come back.
+pub trait Transport: Send + Sync {
+    fn produce(&self, uid: Uuid, utbetaling: Utbetaling) -> BoxFuture<'_, Result<(), ProduceError>>;
+
+    /// Starts delivering status and dryrun replies to the inbox.
+    fn subscribe(&self, inbox: Inbox);
+
+    fn flush(&self) {}
+
+    /// Stops delivering replies, when the service shuts down.
+    fn close(&self) -> BoxFuture<'_, ()> {
+        Box::pin(async {})
+    }
+
+    /// Why the transport can not take traffic yet, empty when it can.
+    fn not_ready(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// What the transport knows about its connections, for `/health/details`.
+    fn details(&self) -> BoxFuture<'_, serde_json::Value> {
+        Box::pin(async { serde_json::Value::Null })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportKind {
+    Kafka,
+    Rest,
+    Memory,
+}
+
+impl TransportKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransportKind::Kafka => "kafka",
+            TransportKind::Rest => "rest",
+            TransportKind::Memory => "memory",
+        }
+    }
+}
+
+impl FromStr for TransportKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "kafka" => Ok(TransportKind::Kafka),
+            "rest" => Ok(TransportKind::Rest),
+            "memory" => Ok(TransportKind::Memory),
+            other => Err(format!("unknown transport {other}, expected kafka, rest or memory")),
+        }
+    }
+}
+
+/// The transports set up for this run, one of them used when nothing else is asked for.
+#[derive(Clone)]
+pub struct Transports {
+    pub default: TransportKind,
+    transports: Vec<(TransportKind, Arc<dyn Transport>)>,
+}
+
+impl Transports {
+    pub fn new(default: TransportKind) -> Self {
+        Self { default, transports: Vec::new() }
+    }
+
+    pub fn with(mut self, kind: TransportKind, transport: Arc<dyn Transport>) -> Self {
+        self.transports.push((kind, transport));
+        self
+    }
+
+    pub fn get(&self, kind: TransportKind) -> Option<&Arc<dyn Transport>> {
+        self.transports.iter().find(|(it, _)| *it == kind).map(|(_, transport)| transport)
+    }
+
+    pub fn subscribe(&self, inbox: &Inbox) {
+        for (_, transport) in &self.transports {
+            transport.subscribe(inbox.clone());
+        }
+    }
+
+    pub fn flush(&self) {
+        for (_, transport) in &self.transports {
+            transport.flush();
+        }
+    }
+
+    pub async fn close(&self) {
+        for (kind, transport) in &self.transports {
+            transport.close().await;
+            info!("Closed {} transport", kind.as_str());
+        }
+    }
+
+    pub fn not_ready(&self) -> Vec<String> {
+        self.transports.iter().flat_map(|(_, transport)| transport.not_ready()).collect()
+    }
+
+    pub async fn details(&self) -> BTreeMap<TransportKind, serde_json::Value> {
+        let mut details = BTreeMap::new();
+        for (kind, transport) in &self.transports {
+            details.insert(*kind, transport.details().await);
+        }
+        details
+    }
+}
+
+#[derive(Debug)]
+pub enum ProduceError {
+    UnknownTopic(String),
+    /// the broker did not ack the record within the produce timeout, or refused it
+    Delivery(String),
+    Unavailable(String),
+    Token(TokenError),
+}
+
+impl ProduceError {
+    /// 502 when the broker answered with an error, 503 when we never got that far.
+    pub fn status_code(&self) -> u16 {
+        match self {
+            ProduceError::Delivery(_) => 502,
+            ProduceError::UnknownTopic(_) | ProduceError::Unavailable(_) | ProduceError::Token(_) => 503,
+        }
+    }
+}
+
+impl fmt::Display for ProduceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProduceError::UnknownTopic(topic) => write!(f, "Fant ikke topic {topic} i metadata fra kafka"),
+            ProduceError::Delivery(msg) => write!(f, "Kafka tok ikke imot utbetalingen: {msg}"),
+            ProduceError::Unavailable(msg) => write!(f, "Fikk ikke kontakt med utsjekk: {msg}"),
+            ProduceError::Token(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// Routes replies to the pending transactions and the result store.
+#[derive(Clone)]
+pub struct Inbox {
+    pub pending: Arc<Registry>,
+    pub results: Arc<ResultStore>,
+    pub metrics: Arc<Metrics>,
+}
+
+impl Inbox {
+    pub fn status(&self, uid: Uuid, reply: Reply) {
+        let status = reply.status;
+        let arrival = self.results.reply(&uid, &reply);
+        let routed = self.pending.status(&uid, reply);
+        self.count(uid, ReplyKind::Status, Some(status), arrival, routed);
+    }
+
+    pub fn simulering(&self, uid: Uuid, simulering: Simulering) {
+        let arrival = self.results.simulering(&uid, &simulering);
+        let routed = self.pending.simulering(&uid, simulering);
+        self.count(uid, ReplyKind::Simulering, None, arrival, routed);
+    }
+
+    /// Replies nobody waited for are kept on the transaction when they came after its
+    /// request gave up, or as orphans when the transaction is unknown.
+    fn count(&self, uid: Uuid, kind: ReplyKind, status: Option<Status>, arrival: Option<Arrival>, routed: bool) {
+        let late = |delay: Option<Duration>| LateReply {
+            kind,
+            status,
+            delay_ms: delay.map(|it| it.as_millis() as u64),
+            orphan: delay.is_none(),
+            arrived: Utc::now(),
+        };
+        match arrival {
+            None if !routed => {
+                self.metrics.orphan(kind);
+                self.results.late(uid, late(None));
+            }
+            Some(Arrival { since_submit: delay, after_deadline: true }) if !routed => {
+                info!("Late {} reply for {uid} after {delay:?}", kind.as_str());
+                self.metrics.reply(kind);
+                self.metrics.late(kind, delay);
+                self.results.late(uid, late(Some(delay)));
+            }
+            _ => self.metrics.reply(kind),
+        }
+    }
+}
+
+/// Keeps everything in process, every produced utbetaling is answered by the
+/// mock utsjekk. Without a mock nothing is answered, which is only useful in tests.
+pub struct InMemory {
+    mock: Option<MockUtsjekk>,
+    inbox: Mutex<Option<Inbox>>,
+}
+
+impl InMemory {
+    pub fn new(mock: Option<MockUtsjekk>) -> Self {
+        Self { mock, inbox: Mutex::new(None) }
+    }
+}
+
+impl Transport for InMemory {
+    fn produce(&self, uid: Uuid, utbetaling: Utbetaling) -> BoxFuture<'_, Result<(), ProduceError>> {
+        let inbox = self.inbox.lock().unwrap().clone();
+        if let (Some(mock), Some(inbox)) = (&self.mock, inbox) {
+            let replies = mock.replies(utbetaling.fagsystem(), utbetaling.dryrun());
+            actix_web::rt::spawn(async move {
+                for (delay, reply) in replies {
+                    actix_web::rt::time::sleep(delay).await;
+                    match reply {
+                        MockReply::Status(reply) => inbox.status(uid, reply),
+                        MockReply::Simulering(simulering) => inbox.simulering(uid, simulering),
+                    }
+                }
+            });
+        }
+        Box::pin(async { Ok(()) })
+    }
+
+    fn subscribe(&self, inbox: Inbox) {
+        *self.inbox.lock().unwrap() = Some(inbox);
+    }
+}